@@ -0,0 +1,71 @@
+//! Lexes a generated multi-megabyte script, comparing the allocations made by
+//! borrowed tokens against giving every token its own `String` as the lexer
+//! used to. Run with `cargo bench --bench lexer_allocations`.
+//!
+//! Counting allocations means replacing the global allocator, which is why
+//! this is a binary of its own rather than one of the lexer's tests.
+
+use rusty_monkey::{lexer::Lexer, token::Token};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations_during<T>(f: impl FnOnce() -> T) -> (usize, T) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let result = f();
+    (ALLOCATIONS.load(Ordering::Relaxed) - before, result)
+}
+
+fn main() {
+    let chunk = r#"let add = fn(x, y) { x + y; };
+let result = add(five, ten) * 0xFF - 1_000 / 3.14;
+if (result != 10) { return "big"; } else { return false; }
+"#;
+    let input = chunk.repeat(4 * 1024 * 1024 / chunk.len());
+
+    let start = Instant::now();
+    let (borrowed_allocations, token_count) = allocations_during(|| Lexer::new(&input).count());
+    let borrowed_time = start.elapsed();
+
+    let start = Instant::now();
+    let (owned_allocations, owned_count) =
+        allocations_during(|| Lexer::new(&input).map(Token::into_owned).count());
+    let owned_time = start.elapsed();
+
+    println!(
+        "{} bytes, {} tokens\n  borrowed: {} allocations in {:?}\n  owned:    {} allocations in {:?}",
+        input.len(),
+        token_count,
+        borrowed_allocations,
+        borrowed_time,
+        owned_allocations,
+        owned_time
+    );
+
+    assert_eq!(token_count, owned_count);
+    assert!(owned_allocations >= token_count);
+    // Only interning an identifier we haven't seen before allocates, so this
+    // doesn't grow with the size of the input.
+    assert!(borrowed_allocations < 100);
+}
@@ -1,4 +1,9 @@
-use crate::token::Token;
+use std::borrow::Cow;
+
+use crate::{
+    symbol::{Interner, Symbol},
+    token::Token,
+};
 
 pub trait Node {
     fn token_literal(&self) -> String;
@@ -8,131 +13,210 @@ pub trait Node {
 /*************
 * Statements *
 *************/
-#[derive(Debug)]
-pub enum Statement {
-    Let(LetStatement),
-    Return(ReturnStatement),
-    Expression(ExpressionStatement),
+#[derive(Debug, Clone)]
+pub enum Statement<'src> {
+    Let(LetStatement<'src>),
+    Return(ReturnStatement<'src>),
+    Expression(ExpressionStatement<'src>),
+    Error(ErrorStatement<'src>),
 }
 
-impl Node for Statement {
+impl Node for Statement<'_> {
     fn token_literal(&self) -> String {
         match self {
-            Statement::Let(s) => s.token.literal.clone(),
-            Statement::Return(s) => s.token.literal.clone(),
-            Statement::Expression(s) => s.token.literal.clone(),
+            Statement::Let(s) => s.token.literal.to_string(),
+            Statement::Return(s) => s.token.literal.to_string(),
+            Statement::Expression(s) => s.token.literal.to_string(),
+            Statement::Error(s) => s.token.literal.to_string(),
         }
     }
     fn string(&self) -> String {
         let mut out = String::new();
         match self {
             Statement::Let(ls) => {
+                ls.docs.iter().for_each(|doc| {
+                    out.push_str("///");
+                    out.push_str(doc);
+                    out.push('\n');
+                });
                 out.push_str(&self.token_literal());
                 out.push(' ');
                 out.push_str(&ls.name.string());
                 out.push_str(" = ");
-
-                // TODO: to be taken out when we can fully build expressions
-                if let Some(val) = &ls.value {
-                    out.push_str(&val.string());
-                }
+                out.push_str(&ls.value.string());
                 out.push(';');
             }
             Statement::Return(rs) => {
-                let mut out = String::new();
                 out.push_str(&self.token_literal());
                 out.push(' ');
-
-                // TODO: to be taken out when we can fully build expressions
-                if let Some(val) = &rs.value {
-                    out.push_str(&val.string());
-                }
+                out.push_str(&rs.value.string());
                 out.push(';');
             }
-            Statement::Expression(es) => {
-                // TODO: to be taken out when we can fully build expressions
-                if let Some(expression) = &es.expression {
-                    out.push_str(&expression.string());
-                }
-            }
+            Statement::Expression(es) => out.push_str(&es.expression.string()),
+            Statement::Error(_) => out.push_str(ERROR_PLACEHOLDER),
         }
 
         out
     }
 }
 
-#[derive(Debug)]
-pub struct LetStatement {
-    pub token: Token,
-    pub name: Expression,          // Should only ever be Expression::Identifier
-    pub value: Option<Expression>, // TODO: temp Option until we parse expressions in Let
+#[derive(Debug, Clone)]
+pub struct LetStatement<'src> {
+    pub token: Token<'src>,
+    pub name: Expression<'src>, // Should only ever be Expression::Identifier
+    pub value: Expression<'src>,
+    pub docs: Vec<Cow<'src, str>>, // Text of each `///` line right before the `let`
 }
-impl LetStatement {
-    pub fn new(token: Token, name: IdentifierStruct, value: Option<Expression>) -> LetStatement {
+impl<'src> LetStatement<'src> {
+    pub fn new(
+        token: Token<'src>,
+        name: IdentifierStruct<'src>,
+        value: Expression<'src>,
+        docs: Vec<Cow<'src, str>>,
+    ) -> LetStatement<'src> {
         LetStatement {
             token,
             name: Expression::Identifier(name),
             value,
+            docs,
         }
     }
 }
 
-#[derive(Debug)]
-pub struct ReturnStatement {
-    token: Token,
-    value: Option<Expression>, // TODO: temp Option until we parse expressions in Return
+#[derive(Debug, Clone)]
+pub struct ReturnStatement<'src> {
+    token: Token<'src>,
+    pub value: Expression<'src>,
 }
-impl ReturnStatement {
-    pub fn new(token: Token, value: Option<Expression>) -> ReturnStatement {
+impl<'src> ReturnStatement<'src> {
+    pub fn new(token: Token<'src>, value: Expression<'src>) -> ReturnStatement<'src> {
         ReturnStatement { token, value }
     }
 }
 
-#[derive(Debug)]
-pub struct ExpressionStatement {
-    token: Token,
-    pub expression: Option<Expression>, // TODO: temp Option until we parse expressions in Return
+#[derive(Debug, Clone)]
+pub struct ExpressionStatement<'src> {
+    token: Token<'src>,
+    pub expression: Expression<'src>,
 }
-impl ExpressionStatement {
-    pub fn new(token: Token, expression: Option<Expression>) -> ExpressionStatement {
+impl<'src> ExpressionStatement<'src> {
+    pub fn new(token: Token<'src>, expression: Expression<'src>) -> ExpressionStatement<'src> {
         ExpressionStatement { token, expression }
     }
 }
 
+/// What `string` shows in place of an `Error` node.
+const ERROR_PLACEHOLDER: &str = "<error>";
+
+/// Stands in for a statement the parser couldn't make sense of, so the rest of
+/// the program is still there for tools to work with. The error itself is
+/// reported as a `Diagnostic`; `token` is where the statement started.
+#[derive(Debug, Clone)]
+pub struct ErrorStatement<'src> {
+    pub token: Token<'src>,
+}
+impl<'src> ErrorStatement<'src> {
+    pub fn new(token: Token<'src>) -> ErrorStatement<'src> {
+        ErrorStatement { token }
+    }
+}
+
+/// The statements between a `{` and its `}`, e.g. the branches of an `if`.
+#[derive(Debug, Clone)]
+pub struct BlockStatement<'src> {
+    token: Token<'src>, // the `{`, or the `if` of an `else if`
+    pub statements: Vec<Statement<'src>>,
+}
+impl<'src> BlockStatement<'src> {
+    pub fn new(token: Token<'src>, statements: Vec<Statement<'src>>) -> BlockStatement<'src> {
+        BlockStatement { token, statements }
+    }
+}
+impl Node for BlockStatement<'_> {
+    fn token_literal(&self) -> String {
+        self.token.literal.to_string()
+    }
+    fn string(&self) -> String {
+        let mut out = String::from('{');
+        self.statements.iter().for_each(|s| {
+            out.push(' ');
+            out.push_str(&s.string());
+        });
+        out.push_str(" }");
+
+        out
+    }
+}
+
 /**************
 * Expressions *
 **************/
 #[derive(Debug, Clone)]
-pub enum Expression {
-    Identifier(IdentifierStruct),
-    IntegerLiteral(IntegerLiteralStruct),
-    PrefixExpression(PrefixExpressionStruct),
-    InfixExpression(InfixExpressionStruct),
-}
-impl Expression {
-    pub fn get_expression(&self) -> Option<IdentifierStruct> {
+#[allow(clippy::enum_variant_names)]
+pub enum Expression<'src> {
+    Identifier(IdentifierStruct<'src>),
+    IntegerLiteral(IntegerLiteralStruct<'src>),
+    FloatLiteral(FloatLiteralStruct<'src>),
+    StringLiteral(StringLiteralStruct<'src>),
+    Boolean(BooleanStruct<'src>),
+    Interpolation(InterpolationStruct<'src>),
+    PrefixExpression(PrefixExpressionStruct<'src>),
+    InfixExpression(InfixExpressionStruct<'src>),
+    IfExpression(IfExpressionStruct<'src>),
+    FunctionLiteral(FunctionLiteralStruct<'src>),
+    CallExpression(CallExpressionStruct<'src>),
+    Error(ErrorStruct<'src>),
+}
+impl<'src> Expression<'src> {
+    pub fn get_expression(&self) -> Option<IdentifierStruct<'src>> {
         match self {
             Expression::Identifier(i) => Some(i.clone()),
             _ => None,
         }
     }
 }
-impl Node for Expression {
+impl Node for Expression<'_> {
     fn token_literal(&self) -> String {
         match self {
-            Expression::Identifier(i) => i.token.literal.clone(),
-            Expression::IntegerLiteral(i) => i.token.literal.clone(),
-            Expression::PrefixExpression(pe) => pe.token.literal.clone(),
-            Expression::InfixExpression(ie) => ie.token.literal.clone(),
+            Expression::Identifier(i) => i.token.literal.to_string(),
+            Expression::IntegerLiteral(i) => i.token.literal.to_string(),
+            Expression::FloatLiteral(f) => f.token.literal.to_string(),
+            Expression::StringLiteral(s) => s.token.literal.to_string(),
+            Expression::Boolean(b) => b.token.literal.to_string(),
+            Expression::Interpolation(i) => i.token.literal.to_string(),
+            Expression::PrefixExpression(pe) => pe.token.literal.to_string(),
+            Expression::InfixExpression(ie) => ie.token.literal.to_string(),
+            Expression::IfExpression(ie) => ie.token.literal.to_string(),
+            Expression::FunctionLiteral(fl) => fl.token.literal.to_string(),
+            Expression::CallExpression(ce) => ce.token.literal.to_string(),
+            Expression::Error(e) => e.token.literal.to_string(),
         }
     }
     fn string(&self) -> String {
         match self {
-            Expression::Identifier(i) => i.value.clone(),
-            Expression::IntegerLiteral(i) => i
-                .value
-                .expect("IntegerLiteralStruct has None value.")
-                .to_string(),
+            Expression::Identifier(i) => i.token.literal.to_string(),
+            Expression::IntegerLiteral(i) => i.value.to_string(),
+            // Debug formatting always keeps a `.0` or exponent, so floats stay floats
+            Expression::FloatLiteral(f) => format!("{:?}", f.value),
+            Expression::StringLiteral(s) => quote_string(&s.value),
+            // The value rather than the literal, which could be a keyword alias
+            Expression::Boolean(b) => b.value.to_string(),
+            Expression::Interpolation(i) => {
+                let mut str_val = String::from('`');
+                for part in &i.parts {
+                    match part {
+                        TemplatePart::Text(text) => push_template_text(&mut str_val, text),
+                        TemplatePart::Expression(e) => {
+                            str_val.push_str("${");
+                            str_val.push_str(&e.string());
+                            str_val.push('}');
+                        }
+                    }
+                }
+                str_val.push('`');
+
+                str_val
+            }
             Expression::PrefixExpression(pe) => {
                 let mut str_val = String::new();
                 str_val.push('(');
@@ -154,40 +238,182 @@ impl Node for Expression {
 
                 str_val
             }
+            Expression::IfExpression(ie) => {
+                let mut str_val = String::from("if ");
+                str_val.push_str(&ie.condition.string());
+                str_val.push(' ');
+                str_val.push_str(&ie.consequence.string());
+
+                if let Some(alternative) = &ie.alternative {
+                    str_val.push_str(" else ");
+                    match ie.else_if() {
+                        Some(else_if) => str_val.push_str(&else_if.string()),
+                        None => str_val.push_str(&alternative.string()),
+                    }
+                }
+
+                str_val
+            }
+            Expression::FunctionLiteral(fl) => {
+                let parameters: Vec<String> = fl.parameters.iter().map(|p| p.string()).collect();
+
+                let mut str_val = String::from("fn(");
+                str_val.push_str(&parameters.join(", "));
+                str_val.push_str(") ");
+                str_val.push_str(&fl.body.string());
+
+                str_val
+            }
+            Expression::CallExpression(ce) => {
+                let arguments: Vec<String> = ce.arguments.iter().map(|a| a.string()).collect();
+
+                let mut str_val = ce.function.string();
+                str_val.push('(');
+                str_val.push_str(&arguments.join(", "));
+                str_val.push(')');
+
+                str_val
+            }
+            Expression::Error(_) => ERROR_PLACEHOLDER.to_string(),
         }
     }
 }
 
+/// The name itself is only kept as a `Symbol`. Use `Program::symbols` to get
+/// its text back.
 #[derive(Debug, Clone)]
-pub struct IdentifierStruct {
-    token: Token,
-    pub value: String,
+pub struct IdentifierStruct<'src> {
+    token: Token<'src>,
+    pub symbol: Symbol,
 }
-impl IdentifierStruct {
-    pub fn new(token: Token, value: String) -> IdentifierStruct {
-        IdentifierStruct { token, value }
+impl<'src> IdentifierStruct<'src> {
+    pub fn new(token: Token<'src>, symbol: Symbol) -> IdentifierStruct<'src> {
+        IdentifierStruct { token, symbol }
     }
 }
 
 #[derive(Debug, Clone)]
-pub struct IntegerLiteralStruct {
-    token: Token,
-    pub value: Option<i64>,
+pub struct IntegerLiteralStruct<'src> {
+    token: Token<'src>,
+    pub value: i64,
 }
-impl IntegerLiteralStruct {
-    pub fn new(token: Token, value: Option<i64>) -> IntegerLiteralStruct {
+impl<'src> IntegerLiteralStruct<'src> {
+    pub fn new(token: Token<'src>, value: i64) -> IntegerLiteralStruct<'src> {
         IntegerLiteralStruct { token, value }
     }
 }
 
 #[derive(Debug, Clone)]
-pub struct PrefixExpressionStruct {
-    token: Token,
-    pub operator: String,
-    pub right: Box<Expression>,
+pub struct FloatLiteralStruct<'src> {
+    token: Token<'src>,
+    pub value: f64,
+}
+impl<'src> FloatLiteralStruct<'src> {
+    pub fn new(token: Token<'src>, value: f64) -> FloatLiteralStruct<'src> {
+        FloatLiteralStruct { token, value }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StringLiteralStruct<'src> {
+    token: Token<'src>,
+    pub value: Cow<'src, str>,
+}
+impl<'src> StringLiteralStruct<'src> {
+    pub fn new(token: Token<'src>, value: Cow<'src, str>) -> StringLiteralStruct<'src> {
+        StringLiteralStruct { token, value }
+    }
+}
+
+/// Stands in for an expression that couldn't be parsed, like
+/// `ErrorStatement`. `token` is where the expression should have started.
+#[derive(Debug, Clone)]
+pub struct ErrorStruct<'src> {
+    pub token: Token<'src>,
+}
+impl<'src> ErrorStruct<'src> {
+    pub fn new(token: Token<'src>) -> ErrorStruct<'src> {
+        ErrorStruct { token }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BooleanStruct<'src> {
+    token: Token<'src>,
+    pub value: bool,
+}
+impl<'src> BooleanStruct<'src> {
+    pub fn new(token: Token<'src>, value: bool) -> BooleanStruct<'src> {
+        BooleanStruct { token, value }
+    }
+}
+
+/// Wraps a string value in double quotes, escaping it so that the lexer would
+/// read it back as the same value.
+fn quote_string(value: &str) -> String {
+    let mut out = String::from('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A template string such as `` `Hello, ${name}!` ``, made up of its text
+/// and the expressions interpolated into it, in order.
+#[derive(Debug, Clone)]
+pub struct InterpolationStruct<'src> {
+    token: Token<'src>,
+    pub parts: Vec<TemplatePart<'src>>,
+}
+impl<'src> InterpolationStruct<'src> {
+    pub fn new(token: Token<'src>, parts: Vec<TemplatePart<'src>>) -> InterpolationStruct<'src> {
+        InterpolationStruct { token, parts }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum TemplatePart<'src> {
+    Text(Cow<'src, str>),
+    Expression(Expression<'src>),
+}
+
+/// Pushes template text onto `out`, escaped so that the lexer would read it
+/// back as the same text. Line breaks and tabs are fine as they are.
+fn push_template_text(out: &mut String, text: &str) {
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '`' => out.push_str("\\`"),
+            '\\' => out.push_str("\\\\"),
+            '$' if chars.peek() == Some(&'{') => out.push_str("\\$"),
+            '\n' | '\r' | '\t' => out.push(ch),
+            c if c.is_control() => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => out.push(c),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PrefixExpressionStruct<'src> {
+    token: Token<'src>,
+    pub operator: Cow<'src, str>,
+    pub right: Box<Expression<'src>>,
 }
-impl PrefixExpressionStruct {
-    pub fn new(token: Token, operator: String, right: Expression) -> PrefixExpressionStruct {
+impl<'src> PrefixExpressionStruct<'src> {
+    pub fn new(
+        token: Token<'src>,
+        operator: Cow<'src, str>,
+        right: Expression<'src>,
+    ) -> PrefixExpressionStruct<'src> {
         PrefixExpressionStruct {
             token,
             operator,
@@ -197,19 +423,19 @@ impl PrefixExpressionStruct {
 }
 
 #[derive(Debug, Clone)]
-pub struct InfixExpressionStruct {
-    token: Token,
-    pub left: Box<Expression>,
-    pub operator: String,
-    pub right: Box<Expression>,
+pub struct InfixExpressionStruct<'src> {
+    token: Token<'src>,
+    pub left: Box<Expression<'src>>,
+    pub operator: Cow<'src, str>,
+    pub right: Box<Expression<'src>>,
 }
-impl InfixExpressionStruct {
+impl<'src> InfixExpressionStruct<'src> {
     pub fn new(
-        token: Token,
-        left: Expression,
-        operator: String,
-        right: Expression,
-    ) -> InfixExpressionStruct {
+        token: Token<'src>,
+        left: Expression<'src>,
+        operator: Cow<'src, str>,
+        right: Expression<'src>,
+    ) -> InfixExpressionStruct<'src> {
         InfixExpressionStruct {
             token,
             left: Box::new(left),
@@ -219,25 +445,107 @@ impl InfixExpressionStruct {
     }
 }
 
+/// `if condition { ... } else { ... }`. An `else if` is parsed as an
+/// alternative block holding nothing but the next `if`, so a chain of them
+/// nests to the right.
+#[derive(Debug, Clone)]
+pub struct IfExpressionStruct<'src> {
+    token: Token<'src>,
+    pub condition: Box<Expression<'src>>,
+    pub consequence: Box<BlockStatement<'src>>,
+    pub alternative: Option<Box<BlockStatement<'src>>>,
+}
+impl<'src> IfExpressionStruct<'src> {
+    pub fn new(
+        token: Token<'src>,
+        condition: Expression<'src>,
+        consequence: BlockStatement<'src>,
+        alternative: Option<BlockStatement<'src>>,
+    ) -> IfExpressionStruct<'src> {
+        IfExpressionStruct {
+            token,
+            condition: Box::new(condition),
+            consequence: Box::new(consequence),
+            alternative: alternative.map(Box::new),
+        }
+    }
+
+    /// The `if` after the `else`, when the alternative is an `else if`.
+    pub fn else_if(&self) -> Option<&Expression<'src>> {
+        match self.alternative.as_ref()?.statements.as_slice() {
+            [Statement::Expression(ExpressionStatement {
+                expression: e @ Expression::IfExpression(_),
+                ..
+            })] => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// `fn(x, y) { ... }`. Each parameter is an `Expression::Identifier`.
+#[derive(Debug, Clone)]
+pub struct FunctionLiteralStruct<'src> {
+    token: Token<'src>,
+    pub parameters: Vec<Expression<'src>>,
+    pub body: Box<BlockStatement<'src>>,
+}
+impl<'src> FunctionLiteralStruct<'src> {
+    pub fn new(
+        token: Token<'src>,
+        parameters: Vec<IdentifierStruct<'src>>,
+        body: BlockStatement<'src>,
+    ) -> FunctionLiteralStruct<'src> {
+        FunctionLiteralStruct {
+            token,
+            parameters: parameters.into_iter().map(Expression::Identifier).collect(),
+            body: Box::new(body),
+        }
+    }
+}
+
+/// `function(arguments)`, where `function` is anything that evaluates to a
+/// function: an identifier, a function literal or another call.
+#[derive(Debug, Clone)]
+pub struct CallExpressionStruct<'src> {
+    token: Token<'src>, // the `(`
+    pub function: Box<Expression<'src>>,
+    pub arguments: Vec<Expression<'src>>,
+}
+impl<'src> CallExpressionStruct<'src> {
+    pub fn new(
+        token: Token<'src>,
+        function: Expression<'src>,
+        arguments: Vec<Expression<'src>>,
+    ) -> CallExpressionStruct<'src> {
+        CallExpressionStruct {
+            token,
+            function: Box::new(function),
+            arguments,
+        }
+    }
+}
+
 /**********
 * Program *
 **********/
-pub struct Program {
-    pub statements: Vec<Statement>,
+#[derive(Debug, Default)]
+pub struct Program<'src> {
+    pub statements: Vec<Statement<'src>>,
+    pub symbols: Interner, // resolves every identifier's `Symbol`
 }
-impl Program {
-    pub fn new() -> Program {
+impl<'src> Program<'src> {
+    pub fn new() -> Program<'src> {
         Program {
             statements: Vec::new(),
+            symbols: Interner::new(),
         }
     }
 }
-impl Node for Program {
+impl Node for Program<'_> {
     fn token_literal(&self) -> String {
-        if self.statements.len() > 0 {
-            self.statements.get(0).unwrap().token_literal()
-        } else {
-            String::new()
+        match self.statements.first() {
+            Some(statement) => statement.token_literal(),
+            None => String::new(),
         }
     }
     fn string(&self) -> String {
@@ -256,32 +564,27 @@ mod tests {
     use super::{Expression, IdentifierStruct, LetStatement, Program, Statement};
     use crate::{
         ast::Node,
+        symbol::Interner,
         token::{Token, TokenType},
     };
 
     #[test]
     fn test_string() {
+        let mut symbols = Interner::new();
         let program = Program {
             statements: vec![Statement::Let(LetStatement {
-                token: Token {
-                    token_type: TokenType::Let,
-                    literal: "let".to_string(),
-                },
+                token: Token::new_from_str(TokenType::Let, "let"),
                 name: Expression::Identifier(IdentifierStruct {
-                    token: Token {
-                        token_type: TokenType::Ident,
-                        literal: "myVar".to_string(),
-                    },
-                    value: "myVar".to_string(),
+                    token: Token::new_from_str(TokenType::Ident, "myVar"),
+                    symbol: symbols.intern("myVar"),
+                }),
+                value: Expression::Identifier(IdentifierStruct {
+                    token: Token::new_from_str(TokenType::Ident, "anotherVar"),
+                    symbol: symbols.intern("anotherVar"),
                 }),
-                value: Some(Expression::Identifier(IdentifierStruct {
-                    token: Token {
-                        token_type: TokenType::Ident,
-                        literal: "anotherVar".to_string(),
-                    },
-                    value: "anotherVar".to_string(),
-                })),
+                docs: Vec::new(),
             })],
+            symbols,
         };
 
         assert_eq!(
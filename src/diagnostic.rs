@@ -0,0 +1,154 @@
+use std::fmt;
+
+use crate::{
+    lexer::LexError,
+    token::{Span, TokenType},
+};
+
+/**
+* Error codes
+*
+* These never change meaning once they're released, so tools can filter on
+* them. Lexer errors start with an `L`, parser errors with a `P`.
+*/
+pub const UNEXPECTED_CHARACTER: &str = "L0001";
+pub const UNTERMINATED_STRING: &str = "L0002";
+pub const UNTERMINATED_RAW_STRING: &str = "L0003";
+pub const UNTERMINATED_MULTILINE_STRING: &str = "L0004";
+pub const INVALID_ESCAPE: &str = "L0005";
+pub const UNTERMINATED_COMMENT: &str = "L0006";
+pub const UNTERMINATED_TEMPLATE: &str = "L0007";
+pub const RESERVED_WORD: &str = "L0008";
+pub const MALFORMED_NUMBER: &str = "L0009";
+pub const UNREADABLE_INPUT: &str = "L0010";
+
+pub const UNEXPECTED_TOKEN: &str = "P0001";
+pub const EXPECTED_EXPRESSION: &str = "P0002";
+pub const INTEGER_OUT_OF_RANGE: &str = "P0003";
+pub const FLOAT_OUT_OF_RANGE: &str = "P0004";
+pub const UNCLOSED_BLOCK: &str = "P0005";
+pub const NESTING_TOO_DEEP: &str = "P0006";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => f.write_str("error"),
+            Severity::Warning => f.write_str("warning"),
+        }
+    }
+}
+
+/// A problem found in the source, by either the lexer or the parser.
+///
+/// `Display` gives the position, message, notes and help as plain text. Tools
+/// that want to render diagnostics their own way can use the fields instead:
+/// `span` is the source the problem is about, and when a particular token was
+/// needed, `expected` lists the token types that would have been fine and
+/// `found` is the one we got.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+    pub expected: Vec<TokenType>,
+    pub found: Option<TokenType>,
+    pub notes: Vec<String>, // extra context, e.g. where an unclosed block started
+    pub help: Option<String>, // a suggestion for fixing the problem
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            span,
+            expected: Vec::new(),
+            found: None,
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    pub fn with_expected(mut self, expected: Vec<TokenType>) -> Diagnostic {
+        self.expected = expected;
+        self
+    }
+
+    pub fn with_found(mut self, found: TokenType) -> Diagnostic {
+        self.found = Some(found);
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Diagnostic {
+        self.help = Some(help.into());
+        self
+    }
+}
+
+impl From<&LexError> for Diagnostic {
+    fn from(error: &LexError) -> Diagnostic {
+        Diagnostic::error(error.code(), error.message(), error.span())
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.span.start, self.message)?;
+        for note in &self.notes {
+            write!(f, "\n  note: {}", note)?;
+        }
+        if let Some(help) = &self.help {
+            write!(f, "\n  help: {}", help)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Diagnostic, Severity, UNEXPECTED_CHARACTER, UNEXPECTED_TOKEN};
+    use crate::{
+        lexer::LexError,
+        token::{Position, Span, TokenType},
+    };
+
+    #[test]
+    fn test_diagnostic_display() {
+        let span = Span::new(Position::new(4, 1, 5), Position::new(5, 1, 6));
+        let diagnostic = Diagnostic::error(UNEXPECTED_TOKEN, "Expected `)`, found `;`", span)
+            .with_expected(vec![TokenType::RParen])
+            .with_found(TokenType::Semicolon)
+            .with_note("the `(` is at 1:1")
+            .with_help("add a `)`");
+
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(
+            diagnostic.to_string(),
+            "1:5: Expected `)`, found `;`\n  note: the `(` is at 1:1\n  help: add a `)`"
+        );
+    }
+
+    #[test]
+    fn test_lex_error_diagnostics() {
+        let span = Span::new(Position::new(4, 1, 5), Position::new(5, 1, 6));
+        let error = LexError::UnexpectedCharacter { ch: '@', span };
+        let diagnostic = Diagnostic::from(&error);
+
+        assert_eq!(diagnostic.code, UNEXPECTED_CHARACTER);
+        assert_eq!(diagnostic.span, span);
+        assert_eq!(diagnostic.to_string(), error.to_string());
+    }
+}
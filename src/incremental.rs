@@ -0,0 +1,660 @@
+use std::{borrow::Cow, mem, ops::Range, sync::Arc};
+
+use crate::{
+    keywords::KeywordTable,
+    lexer::{ends_statement, LexError, Lexer},
+    operators::OperatorTable,
+    symbol::Interner,
+    token::{Position, Span, Token, TokenType, Trivia},
+};
+
+/// A change to some source text: the bytes in `range` are replaced by
+/// `replacement`. This is what an editor hands us on every keystroke.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub replacement: String,
+}
+
+impl TextEdit {
+    pub fn new(range: Range<usize>, replacement: impl Into<String>) -> TextEdit {
+        TextEdit {
+            range,
+            replacement: replacement.into(),
+        }
+    }
+
+    /// Returns `source` with the edit applied.
+    pub fn apply(&self, source: &str) -> String {
+        let mut out = String::with_capacity(source.len() + self.replacement.len());
+        out.push_str(&source[..self.range.start]);
+        out.push_str(&self.replacement);
+        out.push_str(&source[self.range.end..]);
+        out
+    }
+
+    /// How far text after the edit moves, in bytes.
+    fn delta(&self) -> isize {
+        self.replacement.len() as isize - self.range.len() as isize
+    }
+}
+
+/// The token vector for the edited source, as returned by `relex`.
+#[derive(Debug)]
+pub struct Relexed<'src> {
+    /// Every token of the new source, up to and including `Eof`.
+    pub tokens: Vec<Token<'src>>,
+    /// Indices into `tokens` of the tokens that were lexed again. Everything
+    /// outside this range was carried over from the old tokens.
+    pub changed: Range<usize>,
+    /// Indices into the old token vector of the tokens that `changed` replaced.
+    pub replaced: Range<usize>,
+    /// Every problem in the new source: the old errors from before and after
+    /// the re-lexed region, moved along with the tokens, and the ones found
+    /// while re-lexing it.
+    pub errors: Vec<LexError>,
+}
+
+/// How the old tokens were lexed, so `relex` can lex the new ones the same
+/// way.
+#[derive(Debug, Clone)]
+pub struct RelexConfig {
+    /// The interner the old tokens' symbols came from. Identifiers in the new
+    /// tokens are interned into it too.
+    pub interner: Interner,
+    pub keywords: Arc<KeywordTable>,
+    pub operators: Arc<OperatorTable>,
+}
+
+impl Default for RelexConfig {
+    /// An empty interner and the default keywords and operators.
+    fn default() -> RelexConfig {
+        RelexConfig {
+            interner: Interner::new(),
+            keywords: KeywordTable::shared_default(),
+            operators: OperatorTable::shared_default(),
+        }
+    }
+}
+
+/// Updates the tokens of `old_source` for `edit`, lexing as little of
+/// `new_source` (which must be `edit` applied to `old_source`) as we can.
+///
+/// `old_tokens` has to be the full output of `Lexer::tokenize`, `Eof` included,
+/// from either a normal or a lossless lexer, and `old_errors` the errors that
+/// lexer reported. The new tokens are lexed the same way, with the interner,
+/// keywords and operators in `config`.
+///
+/// Lexing restarts a token before the first one the edit could have touched,
+/// since the lexer looks a char or two past the end of a token, or before the
+/// template string that token is in. A lossless token's trailing trivia runs
+/// to the end of its line, so in lossless mode lexing also restarts before
+/// every token whose trailing trivia ends on the line the edit starts on.
+///
+/// Lexing stops as soon as a new token ends, after the edit and outside of any
+/// template, at the same place as one of the old tokens. The lexer is in
+/// exactly the same state there as it was before, so the rest of the old
+/// tokens are reused, just moved to their new positions.
+/// That includes whether a semicolon would be inserted at the next line break,
+/// which depends on the type of the token that ended there. A lossless token
+/// whose trailing trivia crosses a line, e.g. a block comment spanning lines,
+/// is never a place to resync, since whether that line break already inserted
+/// a semicolon depends on where the token itself ended.
+pub fn relex<'new>(
+    old_tokens: &[Token<'_>],
+    old_errors: &[LexError],
+    old_source: &str,
+    edit: &TextEdit,
+    new_source: &'new str,
+    config: &mut RelexConfig,
+) -> Relexed<'new> {
+    debug_assert_eq!(edit.apply(old_source), new_source);
+
+    let lossless = old_tokens.first().is_some_and(|tok| tok.trivia.is_some());
+    let old_ends: Vec<Position> = old_tokens.iter().map(token_end).collect();
+
+    let old_top_level = top_level(old_tokens);
+
+    let mut first = old_ends
+        .iter()
+        .position(|end| end.offset >= edit.range.start)
+        .unwrap_or(old_tokens.len())
+        .saturating_sub(1);
+    while first > 0
+        && (!old_top_level[first - 1]
+            || lossless && same_line(old_source, old_ends[first - 1], edit.range.start))
+    {
+        first -= 1;
+    }
+    let restart = match first {
+        0 => Position::new(0, 1, 1),
+        _ => old_ends[first - 1],
+    };
+
+    let unchanged = Shift::default();
+    let mut tokens: Vec<Token<'new>> = old_tokens[..first]
+        .iter()
+        .map(|tok| unchanged.apply(tok, old_source, new_source))
+        .collect();
+
+    let delta = edit.delta();
+    let edit_end = edit.range.start + edit.replacement.len();
+    let mut lexer = Lexer::new_at(new_source, restart, lossless)
+        .with_interner(mem::take(&mut config.interner))
+        .with_keywords(Arc::clone(&config.keywords))
+        .with_operators(Arc::clone(&config.operators));
+    if first > 0 {
+        let previous = &old_tokens[first - 1];
+        lexer = lexer.with_previous(&previous.token_type, previous.span.end);
+    }
+    let mut old_index = first;
+    let mut resync = None;
+
+    // The old `Eof` can't be a place to resync, since it has nothing after it
+    // to reuse.
+    let last = old_tokens.len().saturating_sub(1);
+
+    loop {
+        let tok = lexer.next_token();
+        let at_eof = tok.token_type == TokenType::Eof;
+        let tok_type = tok.token_type.clone();
+        let end = token_end(&tok);
+        let line_break = trailing_line_break(&tok);
+        tokens.push(tok);
+
+        if at_eof {
+            old_index = old_tokens.len();
+            break;
+        }
+        if end.offset < edit_end {
+            continue;
+        }
+
+        // Past the edit, so the rest of the text is the same as before. If an
+        // old token ended at the same place, the lexer would carry on from
+        // here exactly the way it did last time.
+        let old_offset = end.offset.wrapping_add_signed(-delta);
+        while old_index < last && old_ends[old_index].offset < old_offset {
+            old_index += 1;
+        }
+        if old_index < last
+            && old_ends[old_index].offset == old_offset
+            && old_top_level[old_index]
+            && lexer.modes().is_empty()
+            && !line_break
+            && !trailing_line_break(&old_tokens[old_index])
+            && ends_statement(&old_tokens[old_index].token_type) == ends_statement(&tok_type)
+        {
+            resync = Some(Shift::between(old_ends[old_index], end));
+            old_index += 1;
+            break;
+        }
+    }
+
+    config.interner = lexer.take_interner();
+
+    // An error lies after the end of the token before the one it was found
+    // in, and before the end of that token itself, so the tokens we reused
+    // tell us which of the old errors still hold.
+    let mut errors: Vec<LexError> = old_errors
+        .iter()
+        .filter(|error| error.position().offset < restart.offset)
+        .cloned()
+        .collect();
+    errors.extend_from_slice(lexer.errors());
+
+    let changed = first..tokens.len();
+    if let Some(shift) = resync {
+        tokens.extend(
+            old_tokens[old_index..]
+                .iter()
+                .map(|tok| shift.apply(tok, old_source, new_source)),
+        );
+
+        let reused_from = old_ends[old_index - 1].offset;
+        errors.extend(
+            old_errors
+                .iter()
+                .filter(|error| error.position().offset >= reused_from)
+                .map(|error| {
+                    error
+                        .clone()
+                        .map_positions(|position| shift.position(position))
+                }),
+        );
+    }
+
+    Relexed {
+        tokens,
+        changed,
+        replaced: first..old_index,
+        errors,
+    }
+}
+
+/// Whether the lexer is outside of any template string after each token. The
+/// lexer's state inside a template is more than just where it is in the input,
+/// so we only restart or resync at the top level.
+fn top_level(tokens: &[Token<'_>]) -> Vec<bool> {
+    let mut depth = 0usize;
+    tokens
+        .iter()
+        .map(|tok| {
+            match tok.token_type {
+                TokenType::TemplateStart | TokenType::InterpolationStart => depth += 1,
+                TokenType::TemplateEnd | TokenType::InterpolationEnd => {
+                    depth = depth.saturating_sub(1)
+                }
+                _ => {}
+            }
+            depth == 0
+        })
+        .collect()
+}
+
+/// Whether nothing but the rest of a line lies between `end`, where a lossless
+/// token's trailing trivia ends, and `offset`, so that the trivia could run up
+/// to or past `offset` after an edit there. The trivia itself can already have
+/// crossed a line inside a block comment.
+fn same_line(source: &str, end: Position, offset: usize) -> bool {
+    !source[end.offset..offset].contains(['\n', '\r'])
+}
+
+/// Where the lexer picks up again after `tok`: right after it, or after its
+/// trailing trivia for a lossless token.
+fn token_end(tok: &Token<'_>) -> Position {
+    match &tok.trivia {
+        Some(trivia) => tok.span.end.advanced_over(&trivia.trailing),
+        None => tok.span.end,
+    }
+}
+
+/// Whether a lossless token's trailing trivia has a line break in it.
+fn trailing_line_break(tok: &Token<'_>) -> bool {
+    tok.trivia
+        .as_ref()
+        .is_some_and(|trivia| trivia.trailing.contains(['\n', '\r']))
+}
+
+/// How positions after the edit move. Only positions on the line where we
+/// resynchronized change column; lines after it start at the same column as
+/// before.
+#[derive(Debug, Clone, Copy, Default)]
+struct Shift {
+    offset: isize,
+    line: isize,
+    column: isize,
+    column_line: usize, // the old line whose columns move
+}
+
+impl Shift {
+    fn between(old: Position, new: Position) -> Shift {
+        Shift {
+            offset: new.offset as isize - old.offset as isize,
+            line: new.line as isize - old.line as isize,
+            column: new.column as isize - old.column as isize,
+            column_line: old.line,
+        }
+    }
+
+    fn position(&self, position: Position) -> Position {
+        let column = if position.line == self.column_line {
+            position.column.wrapping_add_signed(self.column)
+        } else {
+            position.column
+        };
+        Position::new(
+            position.offset.wrapping_add_signed(self.offset),
+            position.line.wrapping_add_signed(self.line),
+            column,
+        )
+    }
+
+    /// Moves an old token to where it ends up in the new source, re-borrowing
+    /// its text from the new source. The text after the edit is the same as
+    /// before, so each piece of the token is found where it was in the old
+    /// source, relative to the token's span.
+    fn apply<'new>(&self, tok: &Token<'_>, old_source: &str, new_source: &'new str) -> Token<'new> {
+        let (start, end) = (tok.span.start.offset, tok.span.end.offset);
+        let text = |from: usize, text: &str| self.text(from, text.len(), new_source);
+
+        // The literal is usually part of the lexeme, but it can also be text of
+        // the lexer's own, like the `"\n"` of an inserted semicolon, or a
+        // string with its escapes worked out, which aren't in the source.
+        let literal = match &tok.literal {
+            Cow::Borrowed(literal) => match old_source[start..end].find(literal) {
+                Some(at) => text(start + at, literal),
+                None => Cow::Owned(literal.to_string()),
+            },
+            Cow::Owned(literal) => Cow::Owned(literal.clone()),
+        };
+
+        Token {
+            token_type: tok.token_type.clone(),
+            literal,
+            span: Span::new(self.position(tok.span.start), self.position(tok.span.end)),
+            symbol: tok.symbol,
+            trivia: tok.trivia.as_ref().map(|trivia| {
+                Box::new(Trivia {
+                    leading: text(start - trivia.leading.len(), &trivia.leading),
+                    lexeme: text(start, &trivia.lexeme),
+                    trailing: text(end, &trivia.trailing),
+                })
+            }),
+        }
+    }
+
+    /// The `len` bytes of the new source that were at `start` in the old one.
+    fn text<'new>(&self, start: usize, len: usize, new_source: &'new str) -> Cow<'new, str> {
+        let start = start.wrapping_add_signed(self.offset);
+        Cow::Borrowed(&new_source[start..start + len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{relex, RelexConfig, Relexed, TextEdit};
+    use crate::{
+        keywords::KeywordTable,
+        lexer::Lexer,
+        operators::{Associativity, InfixRule, OperatorTable, LOWEST},
+        symbol::Interner,
+        token::{Token, TokenType},
+    };
+    use std::{borrow::Cow, sync::Arc};
+
+    const SOURCE: &str = "/// The answer
+let five = 5;
+let ten = 10; // ten
+
+let add = fn(x, y) {
+  x + y;
+};
+
+let result = add(five, ten) /* done */;
+let msg = `Hi ${ `${name}` + { 1 } }! ${result}`;
+\"foo\\tbar\" <= 0x1F;
+";
+
+    fn lexer(source: &str, lossless: bool) -> Lexer<'_> {
+        if lossless {
+            Lexer::new_lossless(source)
+        } else {
+            Lexer::new(source)
+        }
+    }
+
+    /// Symbols depend on the order identifiers were first seen in, so tokens
+    /// are compared by the names their symbols stand for.
+    fn describe(tokens: &[Token], interner: &Interner) -> String {
+        let tokens: Vec<_> = tokens
+            .iter()
+            .map(|tok| {
+                let name = tok.symbol.map(|symbol| interner.resolve(symbol));
+                let tok = Token {
+                    symbol: None,
+                    ..tok.clone()
+                };
+                (tok, name)
+            })
+            .collect();
+        format!("{:#?}", tokens)
+    }
+
+    /// Applies `edit` to `source` incrementally and checks that we get the same
+    /// tokens as lexing the new source from scratch, in both lexing modes.
+    fn check_edit(source: &str, edit: TextEdit) -> Relexed<'static> {
+        let new_source: &'static str = Box::leak(edit.apply(source).into_boxed_str());
+        let mut result = None;
+
+        for lossless in [false, true] {
+            let mut l = lexer(source, lossless);
+            let old_tokens = l.tokenize();
+            let mut config = RelexConfig {
+                interner: l.take_interner(),
+                keywords: Arc::clone(l.keywords()),
+                operators: Arc::clone(l.operators()),
+            };
+            let relexed = relex(
+                &old_tokens,
+                l.errors(),
+                source,
+                &edit,
+                new_source,
+                &mut config,
+            );
+
+            let mut l = lexer(new_source, lossless);
+            let expected = l.tokenize();
+
+            assert_eq!(
+                describe(&relexed.tokens, &config.interner),
+                describe(&expected, l.interner()),
+                "relexing {:?} with {:?} (lossless: {}) gave different tokens",
+                source,
+                edit,
+                lossless
+            );
+            assert_eq!(
+                relexed.errors,
+                l.errors(),
+                "relexing {:?} with {:?} (lossless: {}) gave different errors",
+                source,
+                edit,
+                lossless
+            );
+            assert_eq!(
+                relexed.tokens.len() - relexed.changed.len(),
+                old_tokens.len() - relexed.replaced.len(),
+                "changed {:?} and replaced {:?} don't add up",
+                relexed.changed,
+                relexed.replaced
+            );
+            result = Some(relexed);
+        }
+
+        result.unwrap()
+    }
+
+    /// The range of `needle`, which must appear exactly once in `SOURCE`.
+    fn find(needle: &str) -> std::ops::Range<usize> {
+        let start = SOURCE.find(needle).expect("needle not in source");
+        assert_eq!(SOURCE.rfind(needle), Some(start), "needle not unique");
+        start..start + needle.len()
+    }
+
+    #[test]
+    fn test_relex_matches_full_lex() {
+        let tests = vec![
+            // Renaming an identifier
+            TextEdit::new(find("five ="), "six ="),
+            // Growing a token: `=` into `==`
+            TextEdit::new(find("= 5"), "== 5"),
+            // Joining two tokens into one
+            TextEdit::new(find("x + y"), "x +y"),
+            TextEdit::new(find("(x, y)"), "(xy)"),
+            // Comments that swallow everything after them, or nothing at all
+            TextEdit::new(find("let add"), "/* let add"),
+            TextEdit::new(find("let add"), "// let add"),
+            TextEdit::new(find(" // ten"), ""),
+            TextEdit::new(find("/// The"), "//// The"),
+            // An unterminated string
+            TextEdit::new(find("x + y"), "\"x + y"),
+            // Deleting and inserting whole lines
+            TextEdit::new(find("let add = fn(x, y) {\n  x + y;\n};\n"), ""),
+            TextEdit::new(find("let ten"), "let one = 1;\nlet ten"),
+            TextEdit::new(0..0, "let zero = 0;\r\n"),
+            TextEdit::new(SOURCE.len()..SOURCE.len(), "x"),
+            // Edits inside a literal with escapes and a number
+            TextEdit::new(find("\\tbar"), "\\nbaz"),
+            TextEdit::new(find("0x1F"), "0x1G"),
+            // Unexpected chars and non-ASCII text
+            TextEdit::new(find("five, ten"), "fünf, @ten"),
+            TextEdit::new(0..SOURCE.len(), ""),
+            // Template strings
+            TextEdit::new(find("x + y;"), "`x ${y} z`;"),
+            TextEdit::new(find("x + y;"), "`x ${y;"),
+            TextEdit::new(find("0x1F;"), "`${"),
+            TextEdit::new(find("add(five, ten)"), "`${add(five, ten)}`"),
+            TextEdit::new(find("{\n  x"), "{ `\n  x"),
+        ];
+
+        for edit in tests {
+            check_edit(SOURCE, edit);
+        }
+
+        // Resyncing right after a token whose trailing trivia has a line break
+        // in it would reuse a semicolon inserted at that line break.
+        check_edit(
+            "0xélet_x%\r\nelse*true/*\r\n_}#@\\xelse=0b12",
+            TextEdit::new(0..31, ""),
+        );
+
+        // Editing a comment changes the trailing trivia of the token before it
+        // on the same line, however far back that token is.
+        check_edit("x///returne\u{301}-", TextEdit::new(3..9, "\t;_"));
+
+        // Or after a block comment in that token's trailing trivia, which can
+        // cross a line and still reach the edit.
+        check_edit("\"\"/*\r*////", TextEdit::new(9..10, "//"));
+    }
+
+    #[test]
+    fn test_relex_only_lexes_near_the_edit() {
+        let ten = find("ten =").start;
+        let relexed = check_edit(SOURCE, TextEdit::new(ten..ten + 3, "twenty"));
+        assert!(
+            relexed.changed.len() <= 3,
+            "re-lexed too many tokens: {:?}",
+            relexed.changed
+        );
+        assert_eq!(relexed.changed.len(), relexed.replaced.len());
+
+        // Tokens after the edit are moved along but still borrow the source.
+        let ten = relexed
+            .tokens
+            .iter()
+            .rev()
+            .find(|tok| tok.literal == "ten")
+            .unwrap();
+        assert_eq!(ten.span.start.line, 9);
+        assert_eq!(ten.span.start.column, 24);
+        assert!(matches!(ten.literal, Cow::Borrowed(_)));
+
+        // Text that isn't in the source is moved along as it is.
+        let string = relexed
+            .tokens
+            .iter()
+            .find(|tok| tok.token_type == TokenType::String)
+            .unwrap();
+        assert_eq!(string.literal, "foo\tbar");
+        let relexed = check_edit("a\nb\n", TextEdit::new(0..1, "c"));
+        assert_eq!(relexed.changed, 0..1);
+        let semicolon = &relexed.tokens[3];
+        assert_eq!(semicolon.token_type, TokenType::Semicolon);
+        assert_eq!(semicolon.literal, "\n");
+    }
+
+    #[test]
+    fn test_relex_errors() {
+        let relexed = check_edit(SOURCE, TextEdit::new(find("x + y"), "x @ y"));
+        let errors: Vec<String> = relexed.errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(errors, vec!["6:5: Unexpected character '@'"]);
+
+        let relexed = check_edit(SOURCE, TextEdit::new(find("x + y"), "x + y"));
+        assert!(relexed.errors.is_empty());
+
+        // Errors after the edit move along with the tokens
+        let source = "let a = 1;\nlet b = @; let c = \"\\q\";\n";
+        let relexed = check_edit(source, TextEdit::new(4..5, "one = 1;\nlet a"));
+        assert!(relexed.changed.len() < 10);
+        let errors: Vec<String> = relexed.errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "3:9: Unexpected character '@'",
+                "3:21: Invalid escape sequence \\q",
+            ]
+        );
+
+        // And errors in the re-lexed region are replaced
+        let relexed = check_edit(source, TextEdit::new(19..20, "2"));
+        let errors: Vec<String> = relexed.errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(errors, vec!["2:21: Invalid escape sequence \\q"]);
+    }
+
+    #[test]
+    fn test_relex_empty_tokens() {
+        let tokens: Vec<Token> = Vec::new();
+        let relexed = relex(
+            &tokens,
+            &[],
+            "",
+            &TextEdit::new(0..0, "let"),
+            "let",
+            &mut RelexConfig::default(),
+        );
+        assert_eq!(relexed.tokens.len(), 2);
+        assert_eq!(relexed.changed, 0..2);
+        assert_eq!(relexed.replaced, 0..0);
+    }
+
+    #[test]
+    fn test_relex_keywords() {
+        let keywords = Arc::new(KeywordTable::dialect("es").unwrap());
+        let source = "sea x = 1;\nsea y = 2;\n";
+        let mut l = Lexer::new(source).with_keywords(Arc::clone(&keywords));
+        let old_tokens = l.tokenize();
+        let mut config = RelexConfig {
+            interner: l.take_interner(),
+            keywords,
+            ..RelexConfig::default()
+        };
+
+        let edit = TextEdit::new(11..14, "si");
+        let new_source = edit.apply(source);
+        let relexed = relex(
+            &old_tokens,
+            l.errors(),
+            source,
+            &edit,
+            &new_source,
+            &mut config,
+        );
+        let types: Vec<_> = relexed.tokens.iter().map(|tok| &tok.token_type).collect();
+        assert_eq!(types[0], &TokenType::Let);
+        assert_eq!(types[5], &TokenType::If);
+        assert_eq!(relexed.changed, 4..6);
+    }
+
+    #[test]
+    fn test_relex_operators() {
+        let pipe = InfixRule::binary(LOWEST + 5, Associativity::Left);
+        let operators =
+            Arc::new(OperatorTable::default().with_infix(TokenType::Operator("|>".into()), pipe));
+        let source = "x | f;\n";
+        let mut l = Lexer::new(source).with_operators(Arc::clone(&operators));
+        let old_tokens = l.tokenize();
+        let mut config = RelexConfig {
+            interner: l.take_interner(),
+            operators,
+            ..RelexConfig::default()
+        };
+        assert_eq!(l.errors().len(), 1);
+
+        let edit = TextEdit::new(3..3, ">");
+        let new_source = edit.apply(source);
+        let relexed = relex(
+            &old_tokens,
+            l.errors(),
+            source,
+            &edit,
+            &new_source,
+            &mut config,
+        );
+        let types: Vec<_> = relexed.tokens.iter().map(|tok| &tok.token_type).collect();
+        assert_eq!(types[1], &TokenType::Operator("|>".into()));
+        assert_eq!(types.len(), 5);
+        assert!(relexed.errors.is_empty());
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, LazyLock},
+};
+
+use unicode_normalization::UnicodeNormalization;
+
+use crate::token::TokenType;
+
+/// The keywords every dialect starts from.
+const ENGLISH: [(&str, TokenType); 7] = [
+    ("fn", TokenType::Function),
+    ("let", TokenType::Let),
+    ("true", TokenType::True),
+    ("false", TokenType::False),
+    ("if", TokenType::If),
+    ("else", TokenType::Else),
+    ("return", TokenType::Return),
+];
+
+/// Words we expect to turn into keywords later on, so scripts shouldn't use
+/// them as names yet.
+const FUTURE: [&str; 6] = ["while", "for", "match", "const", "struct", "import"];
+
+static DEFAULT: LazyLock<Arc<KeywordTable>> = LazyLock::new(|| Arc::new(KeywordTable::default()));
+
+/// The names dialects can be selected by, e.g. on the command line.
+pub const DIALECTS: [&str; 4] = ["en", "es", "de", "strict"];
+
+/// Which words the lexer reads as keywords rather than identifiers.
+///
+/// Any number of words can stand for the same keyword, so a dialect can add
+/// aliases in another language on top of the English keywords, e.g. `funcion`
+/// for `fn`. Reserved words are lexed as ordinary identifiers, but each use of
+/// one is reported as a `LexError::ReservedWord`.
+///
+/// A lexer shares its table through an `Arc`, since lexers are created all the
+/// time for small pieces of the input (see `StreamLexer` and `relex`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeywordTable {
+    keywords: HashMap<String, TokenType>,
+    reserved: HashSet<String>,
+}
+
+/// What a word read by the lexer turns out to be.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Word {
+    Keyword(TokenType),
+    Reserved,
+    Ident,
+}
+
+impl Default for KeywordTable {
+    /// The English keywords, with nothing reserved.
+    fn default() -> KeywordTable {
+        let mut table = KeywordTable::empty();
+        for (word, token_type) in ENGLISH {
+            table = table.with_alias(word, token_type);
+        }
+        table
+    }
+}
+
+impl KeywordTable {
+    /// A table without any keywords, so every word is an identifier.
+    pub fn empty() -> KeywordTable {
+        KeywordTable {
+            keywords: HashMap::new(),
+            reserved: HashSet::new(),
+        }
+    }
+
+    /// The table lexers use unless they're given another one.
+    pub fn shared_default() -> Arc<KeywordTable> {
+        Arc::clone(&DEFAULT)
+    }
+
+    /// One of the built-in dialects listed in `DIALECTS`:
+    ///
+    /// - `en`: the default English keywords.
+    /// - `es`: Spanish aliases on top of the English keywords.
+    /// - `de`: German aliases on top of the English keywords.
+    /// - `strict`: the English keywords, with likely future keywords reserved.
+    pub fn dialect(name: &str) -> Option<KeywordTable> {
+        let table = KeywordTable::default();
+        let table = match name {
+            "en" => table,
+            "es" => table
+                .with_alias("funcion", TokenType::Function)
+                .with_alias("función", TokenType::Function)
+                .with_alias("sea", TokenType::Let)
+                .with_alias("verdadero", TokenType::True)
+                .with_alias("falso", TokenType::False)
+                .with_alias("si", TokenType::If)
+                .with_alias("sino", TokenType::Else)
+                .with_alias("retornar", TokenType::Return),
+            "de" => table
+                .with_alias("funktion", TokenType::Function)
+                .with_alias("sei", TokenType::Let)
+                .with_alias("wahr", TokenType::True)
+                .with_alias("falsch", TokenType::False)
+                .with_alias("wenn", TokenType::If)
+                .with_alias("sonst", TokenType::Else)
+                .with_alias("zurück", TokenType::Return),
+            "strict" => FUTURE
+                .into_iter()
+                .fold(table, |table, word| table.with_reserved(word)),
+            _ => return None,
+        };
+        Some(table)
+    }
+
+    /// Makes `word` another way of writing the keyword `token_type`. Like the
+    /// identifiers the lexer looks up, it's put in Normalization Form C first,
+    /// so it matches however the source spells its accents.
+    pub fn with_alias(mut self, word: &str, token_type: TokenType) -> KeywordTable {
+        let word: String = word.nfc().collect();
+        self.reserved.remove(&word);
+        self.keywords.insert(word, token_type);
+        self
+    }
+
+    /// Reserves `word`, so using it as a name is reported as an error. It's
+    /// normalized like in `with_alias`.
+    pub fn with_reserved(mut self, word: &str) -> KeywordTable {
+        let word: String = word.nfc().collect();
+        self.keywords.remove(&word);
+        self.reserved.insert(word);
+        self
+    }
+
+    pub fn lookup(&self, word: &str) -> Word {
+        match self.keywords.get(word) {
+            Some(token_type) => Word::Keyword(token_type.clone()),
+            None if self.reserved.contains(word) => Word::Reserved,
+            None => Word::Ident,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeywordTable, Word, DIALECTS};
+    use crate::{
+        lexer::Lexer,
+        token::{Token, TokenType},
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn test_default_keywords() {
+        let table = KeywordTable::default();
+        assert_eq!(table.lookup("fn"), Word::Keyword(TokenType::Function));
+        assert_eq!(table.lookup("return"), Word::Keyword(TokenType::Return));
+        assert_eq!(table.lookup("x"), Word::Ident);
+        assert_eq!(Token::lookup_ident("else"), TokenType::Else);
+        assert_eq!(table.lookup("funcion"), Word::Ident);
+        assert_eq!(table.lookup("while"), Word::Ident);
+    }
+
+    #[test]
+    fn test_dialects() {
+        for name in DIALECTS {
+            let table = KeywordTable::dialect(name).unwrap();
+            assert_eq!(table.lookup("fn"), Word::Keyword(TokenType::Function));
+        }
+        assert_eq!(KeywordTable::dialect("klingon"), None);
+
+        let es = KeywordTable::dialect("es").unwrap();
+        assert_eq!(es.lookup("funcion"), Word::Keyword(TokenType::Function));
+        assert_eq!(es.lookup("sino"), Word::Keyword(TokenType::Else));
+
+        let strict = KeywordTable::dialect("strict").unwrap();
+        assert_eq!(strict.lookup("while"), Word::Reserved);
+        assert_eq!(strict.lookup("whilst"), Word::Ident);
+
+        let table = strict
+            .with_alias("while", TokenType::If)
+            .with_reserved("fn");
+        assert_eq!(table.lookup("while"), Word::Keyword(TokenType::If));
+        assert_eq!(table.lookup("fn"), Word::Reserved);
+    }
+
+    #[test]
+    fn test_aliases_are_normalized() {
+        let table = KeywordTable::default()
+            .with_alias("mientrase\u{301}", TokenType::If)
+            .with_reserved("pra\u{308}fix");
+        assert_eq!(table.lookup("mientrasé"), Word::Keyword(TokenType::If));
+        assert_eq!(table.lookup("präfix"), Word::Reserved);
+
+        let mut l = Lexer::new("mientrase\u{301} mientrasé").with_keywords(Arc::new(table));
+        assert_eq!(l.next_token().token_type, TokenType::If);
+        assert_eq!(l.next_token().token_type, TokenType::If);
+    }
+}
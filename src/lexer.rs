@@ -1,4 +1,7 @@
-use crate::token::*;
+use crate::{diagnostic, keywords::*, operators::OperatorTable, symbol::*, token::*};
+use std::{borrow::Cow, cell::Cell, fmt, io, mem, sync::Arc};
+use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+use unicode_xid::UnicodeXID;
 
 /// Lexer struct that will convert an input string into tokens.
 ///
@@ -9,147 +12,1257 @@ use crate::token::*;
 /// the read_position pointer will carry on forwards to get the full picture of
 /// exactly what the token is.
 ///
-/// Using u8 for the ch field means we only support ASCII. Supporting UTF-8 would
-/// require modifications to how individual characters are read.
-pub struct Lexer {
-    input: String,
+/// Both pointers are byte offsets that always sit on UTF-8 char boundaries, so
+/// slicing `input` between them never splits a codepoint. `read_position` is
+/// advanced by the encoded length of `ch` rather than by one.
+///
+/// The lexer borrows its input rather than owning it, so the tokens it produces
+/// can borrow their literals straight out of the source text. Besides
+/// `next_token`, a `Lexer` is an `Iterator` over every token up to, but not
+/// including, `Eof`.
+///
+/// Identifiers are interned as they're read, so each `Ident` token comes with
+/// a `Symbol`. The interner can be passed in with `with_interner` to share
+/// symbols with earlier lexers, and taken back out with `take_interner`.
+///
+/// Which words are keywords is up to the lexer's `KeywordTable`, which
+/// defaults to the English keywords and can be swapped with `with_keywords`.
+/// Likewise, an `OperatorTable` given to `with_operators` can add operators.
+///
+/// `line` and `column` track where `ch` sits so every token can be given a
+/// `Span`. `\n`, `\r\n` and a lone `\r` each count as a single line break.
+///
+/// Like Go, the lexer inserts semicolons so scripts can leave them out: at a
+/// line break right after a token that can end a statement (see
+/// `ends_statement`), a virtual `Semicolon` token is emitted. It has an empty
+/// span and `"\n"` as its literal.
+///
+/// Template strings are lexed as several tokens: the opening backtick, runs of
+/// text, the `${` and `}` around each interpolated expression, the tokens of
+/// those expressions, and the closing backtick. `modes` keeps track of which of
+/// these we're in the middle of.
+///
+/// `peeked` is how far into the input the lexer has looked so far, which can
+/// be well past the current char, e.g. to find the end of a `"""` string.
+pub struct Lexer<'src> {
+    input: &'src str,
     position: usize,      // current position in input (points to current char)
     read_position: usize, // current reading position in input (after current char)
-    ch: u8,               // current char under examination (byte in Go is an alias for u8)
+    ch: char,             // current char under examination, '\0' once we run off the end
+    line: usize,          // line of ch, 1-based
+    column: usize,        // column of ch in chars, 1-based
+    lossless: bool,       // whether tokens should carry their trivia, see `new_lossless`
+    interner: Interner,
+    keywords: Arc<KeywordTable>,
+    operators: Arc<OperatorTable>,
+    modes: Vec<Mode>, // innermost last, empty outside of any template string
+    semicolon_after: Option<usize>, // line of the last token, if a line break after it ends a statement
+    peeked: Cell<usize>,            // end of the furthest input looked at, see `peeked`
+    errors: Vec<LexError>,
+}
+
+/// Something the lexer is part way through that changes how it reads what
+/// comes next. Templates and interpolations nest inside each other, as in
+/// `` `a ${ `b ${c}` }` ``, so the lexer keeps a stack of these.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mode {
+    /// In the text of a template string that started at `start`.
+    Template { start: Position },
+    /// In a `${ }` interpolation, `depth` levels of `{ }` deep.
+    Interpolation { depth: usize },
+}
+
+/// Problems found while lexing. The lexer records these and carries on, so a
+/// single mistake doesn't stop the rest of the input from being tokenized.
+///
+/// Each problem is reported once, here, and the lexer recovers so the parser
+/// doesn't trip over it again: an unexpected char or a malformed number
+/// becomes a single `Illegal` token that the parser knows not to complain
+/// about.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexError {
+    /// A char that can't start any token, e.g. `@` or `€`.
+    UnexpectedCharacter { ch: char, span: Span },
+    /// A string literal that is still open when the input runs out.
+    UnterminatedString { start: Position },
+    /// A raw string that is still open when the input runs out. `closing` is the
+    /// delimiter it was waiting for, e.g. `"##`.
+    UnterminatedRawString { start: Position, closing: String },
+    /// A `"""` multi-line string that is still open when the input runs out.
+    UnterminatedMultilineString { start: Position },
+    /// A backslash escape we don't know how to interpret, e.g. `\q` or `\u{110000}`.
+    InvalidEscape { escape: String, span: Span },
+    /// A `/*` block comment that is still open when the input runs out.
+    UnterminatedComment { start: Position },
+    /// A template string that is still open when the input runs out.
+    UnterminatedTemplate { start: Position },
+    /// A word the keyword table reserves, used as a name. It's still lexed as
+    /// an identifier.
+    ReservedWord { word: String, span: Span },
+    /// A number that doesn't follow the literal syntax, e.g. `0x`, `1e` or `12abc`.
+    MalformedNumber {
+        literal: String,
+        reason: &'static str,
+        span: Span,
+    },
+    /// Reading the input failed, e.g. a stream that isn't valid UTF-8 or a
+    /// file that can't be read. Only the `StreamLexer` reads its own input.
+    Io {
+        kind: io::ErrorKind,
+        message: String,
+        position: Position,
+    },
 }
 
-impl Lexer {
-    pub fn new(input: String) -> Lexer {
+impl LexError {
+    pub fn position(&self) -> Position {
+        match self {
+            LexError::UnexpectedCharacter { span, .. } => span.start,
+            LexError::UnterminatedString { start } => *start,
+            LexError::UnterminatedRawString { start, .. } => *start,
+            LexError::UnterminatedMultilineString { start } => *start,
+            LexError::InvalidEscape { span, .. } => span.start,
+            LexError::UnterminatedComment { start } => *start,
+            LexError::UnterminatedTemplate { start } => *start,
+            LexError::ReservedWord { span, .. } => span.start,
+            LexError::MalformedNumber { span, .. } => span.start,
+            LexError::Io { position, .. } => *position,
+        }
+    }
+
+    /// The source text the error is about. Errors that are only known by
+    /// where they start, like an unterminated string, get an empty span there.
+    pub fn span(&self) -> Span {
+        match self {
+            LexError::UnexpectedCharacter { span, .. }
+            | LexError::InvalidEscape { span, .. }
+            | LexError::ReservedWord { span, .. }
+            | LexError::MalformedNumber { span, .. } => *span,
+            _ => Span::new(self.position(), self.position()),
+        }
+    }
+
+    /// A stable code for the kind of error, see the `diagnostic` module.
+    pub fn code(&self) -> &'static str {
+        match self {
+            LexError::UnexpectedCharacter { .. } => diagnostic::UNEXPECTED_CHARACTER,
+            LexError::UnterminatedString { .. } => diagnostic::UNTERMINATED_STRING,
+            LexError::UnterminatedRawString { .. } => diagnostic::UNTERMINATED_RAW_STRING,
+            LexError::UnterminatedMultilineString { .. } => {
+                diagnostic::UNTERMINATED_MULTILINE_STRING
+            }
+            LexError::InvalidEscape { .. } => diagnostic::INVALID_ESCAPE,
+            LexError::UnterminatedComment { .. } => diagnostic::UNTERMINATED_COMMENT,
+            LexError::UnterminatedTemplate { .. } => diagnostic::UNTERMINATED_TEMPLATE,
+            LexError::ReservedWord { .. } => diagnostic::RESERVED_WORD,
+            LexError::MalformedNumber { .. } => diagnostic::MALFORMED_NUMBER,
+            LexError::Io { .. } => diagnostic::UNREADABLE_INPUT,
+        }
+    }
+
+    /// What went wrong, without the position `Display` puts in front of it.
+    pub fn message(&self) -> String {
+        match self {
+            LexError::UnexpectedCharacter { ch, .. } => {
+                format!("Unexpected character '{}'", ch.escape_debug())
+            }
+            LexError::UnterminatedString { .. } => "Unterminated string literal".to_string(),
+            LexError::UnterminatedRawString { closing, .. } => {
+                format!("Unterminated raw string, expected a closing {}", closing)
+            }
+            LexError::UnterminatedMultilineString { .. } => {
+                "Unterminated multi-line string, expected a closing \"\"\"".to_string()
+            }
+            LexError::InvalidEscape { escape, .. } => {
+                format!("Invalid escape sequence {}", escape)
+            }
+            LexError::UnterminatedComment { .. } => "Unterminated block comment".to_string(),
+            LexError::UnterminatedTemplate { .. } => "Unterminated template string".to_string(),
+            LexError::ReservedWord { word, .. } => format!("'{}' is a reserved word", word),
+            LexError::MalformedNumber {
+                literal, reason, ..
+            } => format!("Malformed number {}: {}", literal, reason),
+            LexError::Io { message, .. } => format!("Could not read input: {}", message),
+        }
+    }
+
+    /// Moves the error `by` bytes further into the input, for errors found in
+    /// a piece of text that doesn't start at the beginning of the input.
+    pub fn offset_by(self, by: isize) -> LexError {
+        self.map_positions(|position| position.offset_by(by))
+    }
+
+    /// The same error with `f` applied to every position in it.
+    pub fn map_positions(mut self, f: impl Fn(Position) -> Position) -> LexError {
+        match &mut self {
+            LexError::UnexpectedCharacter { span, .. }
+            | LexError::InvalidEscape { span, .. }
+            | LexError::ReservedWord { span, .. }
+            | LexError::MalformedNumber { span, .. } => {
+                *span = Span::new(f(span.start), f(span.end))
+            }
+            LexError::UnterminatedString { start }
+            | LexError::UnterminatedRawString { start, .. }
+            | LexError::UnterminatedMultilineString { start }
+            | LexError::UnterminatedComment { start }
+            | LexError::UnterminatedTemplate { start } => *start = f(*start),
+            LexError::Io { position, .. } => *position = f(*position),
+        }
+        self
+    }
+}
+
+impl Mode {
+    /// Moves the mode `by` bytes further into the input, like
+    /// `LexError::offset_by`.
+    pub fn offset_by(self, by: isize) -> Mode {
+        match self {
+            Mode::Template { start } => Mode::Template {
+                start: start.offset_by(by),
+            },
+            mode => mode,
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.position(), self.message())
+    }
+}
+
+impl<'src> Lexer<'src> {
+    pub fn new(input: &'src str) -> Lexer<'src> {
         let mut l = Lexer {
             input,
             position: 0,
             read_position: 0,
-            ch: 0, // null byte in ascii
+            ch: '\0',
+            line: 1,
+            column: 1,
+            lossless: false,
+            interner: Interner::new(),
+            keywords: KeywordTable::shared_default(),
+            operators: OperatorTable::shared_default(),
+            modes: Vec::new(),
+            semicolon_after: None,
+            peeked: Cell::new(0),
+            errors: Vec::new(),
         };
         l.read_char();
-        return l;
+        l
+    }
+
+    /// Creates a lexer whose tokens also carry the source text around them, for
+    /// tooling like formatters that needs to reproduce the input exactly.
+    ///
+    /// Every token gets `Trivia` holding its `lexeme`, the exact source text it
+    /// was read from, plus any whitespace and comments around it. `trailing`
+    /// trivia runs up to, but not including, the end of the token's line;
+    /// everything after that is `leading` trivia of the next token, and
+    /// whatever is left at the end of the input belongs to `Eof`. So for every
+    /// token up to and including `Eof`, concatenating
+    /// `leading + lexeme + trailing` gives back the input.
+    pub fn new_lossless(input: &'src str) -> Lexer<'src> {
+        let mut l = Lexer::new(input);
+        l.lossless = true;
+        l
+    }
+
+    /// Creates a lexer that starts part way through `input`, at `start`, as if
+    /// it had already lexed everything before it. `start` has to be a position
+    /// between two tokens, such as the end of one token's trailing trivia.
+    pub fn new_at(input: &'src str, start: Position, lossless: bool) -> Lexer<'src> {
+        let mut l = Lexer::new(input);
+        l.position = start.offset;
+        l.read_position = start.offset;
+        l.line = start.line;
+        l.column = start.column;
+        l.lossless = lossless;
+        l.read_char();
+        l
+    }
+
+    /// Makes the lexer intern identifiers into `interner`, e.g. to keep symbols
+    /// the same as in tokens from a previous lexer.
+    pub fn with_interner(mut self, interner: Interner) -> Lexer<'src> {
+        self.interner = interner;
+        self
+    }
+
+    pub fn interner(&self) -> &Interner {
+        &self.interner
+    }
+
+    /// Takes the interner holding every identifier lexed so far, leaving an
+    /// empty one in its place.
+    pub fn take_interner(&mut self) -> Interner {
+        mem::take(&mut self.interner)
+    }
+
+    /// Makes the lexer read keywords from `keywords` instead of the default
+    /// table, e.g. to accept the keywords of another language.
+    pub fn with_keywords(mut self, keywords: Arc<KeywordTable>) -> Lexer<'src> {
+        self.keywords = keywords;
+        self
+    }
+
+    pub fn keywords(&self) -> &Arc<KeywordTable> {
+        &self.keywords
+    }
+
+    /// Makes the lexer read the operators in `operators` (see
+    /// `OperatorTable::spellings`) on top of its own. A `Parser` over the
+    /// lexer parses them with the same table.
+    pub fn with_operators(mut self, operators: Arc<OperatorTable>) -> Lexer<'src> {
+        self.operators = operators;
+        self
+    }
+
+    pub fn operators(&self) -> &Arc<OperatorTable> {
+        &self.operators
+    }
+
+    /// Makes the lexer carry on as if the last token it produced was of type
+    /// `token_type` and ended at `end`, e.g. when resuming with `new_at`, so
+    /// that a semicolon is inserted after it if need be.
+    pub fn with_previous(mut self, token_type: &TokenType, end: Position) -> Lexer<'src> {
+        self.semicolon_after =
+            (ends_statement(token_type) && self.modes.is_empty()).then_some(end.line);
+        self
+    }
+
+    /// Makes the lexer carry on inside the given templates and interpolations,
+    /// e.g. when resuming with `new_at` from a point inside a template string.
+    pub fn with_modes(mut self, modes: Vec<Mode>) -> Lexer<'src> {
+        self.modes = modes;
+        self
+    }
+
+    /// The templates and interpolations the lexer is inside of, innermost last.
+    pub fn modes(&self) -> &[Mode] {
+        &self.modes
+    }
+
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        self.interner.intern(name)
+    }
+
+    /// Lexes the rest of the input into a vector. Unlike iterating over the
+    /// lexer, the final `Eof` token is included.
+    pub fn tokenize(&mut self) -> Vec<Token<'src>> {
+        let mut tokens = Vec::new();
+        loop {
+            let tok = self.next_token();
+            let at_eof = tok.token_type == TokenType::Eof;
+            tokens.push(tok);
+            if at_eof {
+                return tokens;
+            }
+        }
+    }
+
+    pub fn errors(&self) -> &[LexError] {
+        &self.errors
+    }
+
+    /// The byte offset just past the furthest input the lexer has had to look
+    /// at. If it's at or past the end of the input, the lexer would have read
+    /// more if there was any, so what it made of the input so far could change
+    /// once more follows. `StreamLexer` uses this to know when to read more.
+    pub fn peeked(&self) -> usize {
+        self.peeked.get()
+    }
+
+    fn saw(&self, end: usize) {
+        self.peeked.set(self.peeked.get().max(end));
+    }
+
+    /// Whether the input continues with `pattern` at `offset`.
+    fn input_starts_with(&self, offset: usize, pattern: &str) -> bool {
+        self.saw(offset + pattern.len());
+        self.input[offset..].starts_with(pattern)
     }
 
     fn read_char(&mut self) {
-        if self.read_position >= self.input.len() {
-            self.ch = 0;
-        } else {
-            self.ch = self.input.as_bytes()[self.read_position];
+        // Only move the line/column along when we're stepping over a real char,
+        // not on the very first read or once we're already at the end.
+        if self.read_position > self.position {
+            match self.ch {
+                '\n' => self.new_line(),
+                '\r' if self.peek_char() != '\n' => self.new_line(),
+                _ => self.column += 1,
+            }
         }
+
         self.position = self.read_position;
-        self.read_position += 1;
+        match self.input[self.read_position..].chars().next() {
+            Some(ch) => {
+                self.ch = ch;
+                self.read_position += ch.len_utf8();
+            }
+            None => self.ch = '\0',
+        }
+        self.saw(self.read_position);
+    }
+
+    fn peek_char(&self) -> char {
+        let ch = self.input[self.read_position..].chars().next();
+        self.saw(self.read_position + ch.map_or(0, char::len_utf8));
+        ch.unwrap_or('\0')
+    }
+
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.column = 1;
+    }
+
+    fn current_position(&self) -> Position {
+        Position::new(self.position, self.line, self.column)
+    }
+
+    fn at_eof(&self) -> bool {
+        self.position >= self.input.len()
+    }
+
+    /// The source text from `from` up to the current char. Unlike slicing
+    /// `self.input` directly, the result isn't tied to a borrow of `self`.
+    fn slice_from(&self, from: usize) -> &'src str {
+        let input = self.input;
+        &input[from..self.position]
+    }
+
+    /// Reads an identifier and returns it in Unicode Normalization Form C, so
+    /// that e.g. a precomposed `é` and `e` + combining acute name the same thing.
+    fn read_identifier(&mut self) -> Cow<'src, str> {
+        let position = self.position;
+        self.read_char();
+        while is_identifier_continue(self.ch) {
+            self.read_char();
+        }
+
+        let ident = self.slice_from(position);
+        match is_nfc_quick(ident.chars()) {
+            IsNormalized::Yes => Cow::Borrowed(ident),
+            _ => Cow::Owned(ident.nfc().collect()),
+        }
+    }
+
+    /// Reads a number literal and works out whether it's an `Int` or a `Float`.
+    ///
+    /// Everything that could plausibly be part of the number is consumed, even
+    /// stray letters, so that e.g. `0b102` or `12abc` is reported as one
+    /// malformed number rather than being split into several tokens. Malformed
+    /// numbers come back as `Illegal`.
+    fn read_number(&mut self) -> (TokenType, &'src str) {
+        let start = self.current_position();
+        let prefixed =
+            self.ch == '0' && matches!(self.peek_char(), 'x' | 'X' | 'o' | 'O' | 'b' | 'B');
+
+        let mut seen_dot = false;
+        loop {
+            if is_identifier_continue(self.ch) {
+                let ch = self.ch;
+                self.read_char();
+
+                // An exponent can have a sign, as in `1e-9`. Hex digits include
+                // 'e', so this only applies to decimal numbers.
+                if !prefixed
+                    && matches!(ch, 'e' | 'E')
+                    && matches!(self.ch, '+' | '-')
+                    && is_digit(self.peek_char())
+                {
+                    self.read_char();
+                }
+            } else if !prefixed && !seen_dot && self.ch == '.' && is_digit(self.peek_char()) {
+                seen_dot = true;
+                self.read_char();
+            } else {
+                break;
+            }
+        }
+
+        let literal = self.slice_from(start.offset);
+        match classify_number(literal) {
+            Ok(token_type) => (token_type, literal),
+            Err(reason) => {
+                self.errors.push(LexError::MalformedNumber {
+                    literal: literal.to_string(),
+                    reason,
+                    span: Span::new(start, self.current_position()),
+                });
+                (TokenType::Illegal, literal)
+            }
+        }
+    }
+
+    /// Reads a `"` delimited string, starting on the opening quote, and returns
+    /// its contents with escape sequences resolved. An unterminated string is
+    /// reported and whatever was read up to the end of the input is returned.
+    ///
+    /// The contents are only copied out of the input once we hit an escape.
+    fn read_string(&mut self) -> Cow<'src, str> {
+        let start = self.current_position();
+
+        self.read_char();
+        let contents_start = self.position;
+        let mut unescaped: Option<String> = None;
+        let contents = loop {
+            match self.ch {
+                '"' => {
+                    let contents = self.slice_from(contents_start);
+                    self.read_char();
+                    break contents;
+                }
+                '\0' if self.at_eof() => {
+                    self.errors.push(LexError::UnterminatedString { start });
+                    break self.slice_from(contents_start);
+                }
+                '\\' => {
+                    let value = unescaped.get_or_insert_with(|| {
+                        self.input[contents_start..self.position].to_string()
+                    });
+                    self.read_escape(value);
+                }
+                ch => {
+                    if let Some(value) = unescaped.as_mut() {
+                        value.push(ch);
+                    }
+                    self.read_char();
+                }
+            }
+        };
+
+        match unescaped {
+            Some(value) => Cow::Owned(value),
+            None => Cow::Borrowed(contents),
+        }
+    }
+
+    /// `r"`, or `r` followed by any number of `#` and then `"`. Otherwise the
+    /// `r` is just the start of an identifier.
+    fn at_raw_string(&self) -> bool {
+        let rest = self.input[self.read_position..].trim_start_matches('#');
+        self.saw(self.input.len() - rest.len() + 1);
+        rest.starts_with('"')
+    }
+
+    /// Reads a raw string, starting on the `r`, and returns its contents. Raw
+    /// strings have no escapes. They end at the first `"` followed by as many
+    /// `#` as there were after the `r`, so `r#"say "hi""#` is `say "hi"`.
+    fn read_raw_string(&mut self) -> &'src str {
+        let start = self.current_position();
+
+        self.read_char();
+        let hashes_start = self.position;
+        while self.ch == '#' {
+            self.read_char();
+        }
+        let hashes = self.slice_from(hashes_start);
+        self.read_char();
+
+        let contents_start = self.position;
+        loop {
+            if self.ch == '"' && self.input_starts_with(self.read_position, hashes) {
+                let contents = self.slice_from(contents_start);
+                self.advance_to(self.read_position + hashes.len());
+                return contents;
+            }
+            if self.at_eof() {
+                self.errors.push(LexError::UnterminatedRawString {
+                    start,
+                    closing: format!("\"{}", hashes),
+                });
+                return self.slice_from(contents_start);
+            }
+            self.read_char();
+        }
     }
 
-    fn peek_char(&self) -> u8 {
-        if self.read_position >= self.input.len() {
-            0
+    /// Reads a `"""` multi-line string, starting on the opening quotes, and
+    /// returns its contents with escapes resolved and indentation stripped.
+    ///
+    /// If the opening quotes are the last thing on their line, the string
+    /// starts on the next line, and if the closing quotes are the first thing
+    /// on theirs, it ends with the line before. The indentation that all of
+    /// the lines (and the closing quotes) have in common is removed from each
+    /// of them, and line breaks become `\n`:
+    ///
+    /// ```text
+    /// let query = """
+    ///     SELECT *
+    ///       FROM t
+    ///     """;
+    /// ```
+    ///
+    /// gives `"SELECT *\n  FROM t"`.
+    fn read_multiline_string(&mut self) -> Cow<'src, str> {
+        let start = self.current_position();
+        self.advance_to(self.position + MULTILINE_QUOTES.len());
+
+        // The indentation to strip depends on every line, so we have to find
+        // the end before we can read the contents.
+        let contents_start = self.position;
+        let close = find_multiline_end(&self.input[contents_start..]);
+        self.saw(match close {
+            Some(len) => contents_start + len + MULTILINE_QUOTES.len(),
+            None => self.input.len(),
+        });
+        let close = match close {
+            Some(len) => contents_start + len,
+            None => {
+                self.errors
+                    .push(LexError::UnterminatedMultilineString { start });
+                self.advance_to(self.input.len());
+                return Cow::Borrowed(self.slice_from(contents_start));
+            }
+        };
+        let layout = MultilineLayout::new(&self.input[contents_start..close]);
+
+        let mut value = String::new();
+        self.advance_to(contents_start + layout.body.start);
+        let body_end = contents_start + layout.body.end;
+        let mut line_start = true;
+        while self.position < body_end {
+            if line_start {
+                line_start = false;
+                let indent_end = (self.position + layout.indent.len()).min(body_end);
+                while self.position < indent_end && matches!(self.ch, ' ' | '\t') {
+                    self.read_char();
+                }
+                continue;
+            }
+
+            match self.ch {
+                '\r' | '\n' => {
+                    if self.ch == '\r' && self.peek_char() == '\n' {
+                        self.read_char();
+                    }
+                    self.read_char();
+                    value.push('\n');
+                    line_start = true;
+                }
+                '\\' => self.read_escape(&mut value),
+                ch => {
+                    value.push(ch);
+                    self.read_char();
+                }
+            }
+        }
+
+        self.advance_to(close + MULTILINE_QUOTES.len());
+        Cow::Owned(value)
+    }
+
+    /// Reads chars until `position` reaches `offset`, keeping the line and
+    /// column up to date.
+    fn advance_to(&mut self, offset: usize) {
+        while self.position < offset && !self.at_eof() {
+            self.read_char();
+        }
+    }
+
+    /// Reads an escape sequence, starting on the backslash, and pushes the char
+    /// it stands for onto `out`. Supports `\n`, `\r`, `\t`, `\\`, `\"`, and
+    /// `` \` `` and `\$` for template strings, as well as `\u{...}` with one to
+    /// six hex digits.
+    fn read_escape(&mut self, out: &mut String) {
+        let start = self.current_position();
+        self.read_char();
+
+        let escaped = match self.ch {
+            'n' => Some('\n'),
+            'r' => Some('\r'),
+            't' => Some('\t'),
+            '\\' => Some('\\'),
+            '"' => Some('"'),
+            '`' => Some('`'),
+            '$' => Some('$'),
+            'u' => return self.read_unicode_escape(start, out),
+            _ => None,
+        };
+
+        match escaped {
+            Some(ch) => {
+                out.push(ch);
+                self.read_char();
+            }
+            None => {
+                if !self.at_eof() {
+                    self.read_char();
+                }
+                self.invalid_escape(start);
+            }
+        }
+    }
+
+    fn read_unicode_escape(&mut self, start: Position, out: &mut String) {
+        // Step past the 'u'
+        self.read_char();
+        if self.ch != '{' {
+            return self.invalid_escape(start);
+        }
+        self.read_char();
+
+        let digits_start = self.position;
+        while self.ch.is_ascii_hexdigit() {
+            self.read_char();
+        }
+        let digits_end = self.position;
+
+        if self.ch != '}' {
+            return self.invalid_escape(start);
+        }
+        self.read_char();
+
+        let digits = &self.input[digits_start..digits_end];
+        let ch = if digits.is_empty() || digits.len() > 6 {
+            None
         } else {
-            self.input.as_bytes()[self.read_position]
+            u32::from_str_radix(digits, 16)
+                .ok()
+                .and_then(char::from_u32)
+        };
+        match ch {
+            Some(ch) => out.push(ch),
+            None => self.invalid_escape(start),
+        }
+    }
+
+    fn invalid_escape(&mut self, start: Position) {
+        let escape = self.input[start.offset..self.position].to_string();
+        let span = Span::new(start, self.current_position());
+        self.errors.push(LexError::InvalidEscape { escape, span });
+    }
+
+    /// Skips whitespace as well as `//` line comments and `/* */` block
+    /// comments. `///` doc comments are left alone since they become tokens.
+    fn skip_whitespace(&mut self) {
+        self.skip_trivia(true);
+    }
+
+    /// Like `skip_whitespace`, but stops at the end of the current line. Block
+    /// comments that start on this line are skipped in full.
+    fn skip_trailing_trivia(&mut self) {
+        self.skip_trivia(false);
+    }
+
+    fn skip_trivia(&mut self, skip_newlines: bool) {
+        loop {
+            match self.ch {
+                ' ' | '\t' => self.read_char(),
+                '\n' | '\r' if skip_newlines => self.read_char(),
+                '/' if self.peek_char() == '/' && !self.at_doc_comment() => {
+                    self.read_line_comment();
+                }
+                '/' if self.peek_char() == '*' => self.skip_block_comment(),
+                _ => break,
+            }
         }
     }
 
-    fn read_identifier(&mut self) -> &str {
+    /// `///` starts a doc comment, but like in Rust, four or more slashes is
+    /// just an ordinary comment.
+    fn at_doc_comment(&self) -> bool {
+        self.input_starts_with(self.position, "///")
+            && !self.input_starts_with(self.position, "////")
+    }
+
+    /// Reads a `//` comment up to, but not including, the line break and
+    /// returns it, slashes included.
+    fn read_line_comment(&mut self) -> &'src str {
         let position = self.position;
-        while is_letter(self.ch) {
+        while self.ch != '\n' && self.ch != '\r' && !self.at_eof() {
             self.read_char();
         }
 
-        &self.input[position..self.position]
+        self.slice_from(position)
+    }
+
+    /// Skips a block comment, starting on the `/*`. Block comments nest, so
+    /// `/* a /* b */ c */` is a single comment.
+    fn skip_block_comment(&mut self) {
+        let start = self.current_position();
+        let mut depth = 0;
+
+        loop {
+            if self.ch == '/' && self.peek_char() == '*' {
+                depth += 1;
+                self.read_char();
+            } else if self.ch == '*' && self.peek_char() == '/' {
+                depth -= 1;
+                self.read_char();
+            } else if self.at_eof() {
+                self.errors.push(LexError::UnterminatedComment { start });
+                return;
+            }
+            self.read_char();
+
+            if depth == 0 {
+                return;
+            }
+        }
+    }
+
+    /// Reports the current char, which can't start any token, and reads it as
+    /// an `Illegal` token of its own.
+    fn unexpected_char(&mut self) -> Token<'src> {
+        let start = self.current_position();
+        let ch = self.ch;
+        self.read_char();
+        self.errors.push(LexError::UnexpectedCharacter {
+            ch,
+            span: Span::new(start, self.current_position()),
+        });
+
+        Token::new_from_str(TokenType::Illegal, self.slice_from(start.offset))
     }
 
-    fn read_number(&mut self) -> &str {
+    /// Reads the longest operator or delimiter in `OPERATORS`, or added by the
+    /// `OperatorTable`, that the input continues with, if there is one.
+    fn read_operator(&mut self) -> Option<Token<'src>> {
+        let added = self
+            .operators
+            .spellings()
+            .filter(|operator| self.input_starts_with(self.position, operator))
+            .map(|operator| (operator.len(), TokenType::Operator(Arc::clone(operator))));
+        let built_in = OPERATORS
+            .iter()
+            .filter(|(operator, _)| self.input_starts_with(self.position, operator))
+            .map(|(operator, token_type)| (operator.len(), token_type.clone()));
+        // Of the longest ones, `max_by_key` picks the last, so a built-in
+        // operator wins over one added with the same spelling.
+        let (len, token_type) = added.chain(built_in).max_by_key(|(len, _)| *len)?;
+
         let position = self.position;
-        while is_digit(self.ch) {
+        while self.position < position + len {
             self.read_char();
         }
 
-        &self.input[position..self.position]
+        Some(Token::new_from_str(token_type, self.slice_from(position)))
     }
 
-    fn skip_whitespace(&mut self) {
-        while self.ch as char == ' '
-            || self.ch as char == '\t'
-            || self.ch as char == '\n'
-            || self.ch as char == '\r'
-        {
+    /// Whether a statement whose last token ended on `line` ends here: there's
+    /// nothing left on the line but whitespace and comments. A block comment
+    /// that spans lines counts as a line break, and so does a doc comment,
+    /// which runs to the end of the line.
+    fn at_statement_end(&mut self, line: usize) -> bool {
+        self.skip_trailing_trivia();
+        self.line > line || matches!(self.ch, '\n' | '\r') || self.at_doc_comment()
+    }
+
+    /// Reads the next piece of a template string: the closing backtick, the
+    /// `${` of an interpolation, or the text up to the next of those.
+    fn read_template_part(&mut self) -> Token<'src> {
+        let start = self.position;
+
+        if self.ch == '`' {
+            self.modes.pop();
+            self.read_char();
+            return Token::new_from_str(TokenType::TemplateEnd, self.slice_from(start));
+        }
+        if self.at_interpolation() {
+            self.modes.push(Mode::Interpolation { depth: 0 });
             self.read_char();
+            self.read_char();
+            return Token::new_from_str(TokenType::InterpolationStart, self.slice_from(start));
+        }
+
+        let mut unescaped: Option<String> = None;
+        while self.ch != '`' && !self.at_interpolation() && !self.at_eof() {
+            if self.ch == '\\' {
+                let value =
+                    unescaped.get_or_insert_with(|| self.input[start..self.position].to_string());
+                self.read_escape(value);
+            } else {
+                if let Some(value) = unescaped.as_mut() {
+                    value.push(self.ch);
+                }
+                self.read_char();
+            }
+        }
+
+        let text = match unescaped {
+            Some(value) => Cow::Owned(value),
+            None => Cow::Borrowed(self.slice_from(start)),
+        };
+        Token::new(TokenType::TemplateText, text, Span::default())
+    }
+
+    fn at_interpolation(&self) -> bool {
+        self.ch == '$' && self.peek_char() == '{'
+    }
+
+    /// Inside an interpolation, braces have to be counted to know whether a `}`
+    /// closes a block in the expression or the interpolation itself.
+    fn track_braces(&mut self, mut tok: Token<'src>) -> Token<'src> {
+        match (&tok.token_type, self.modes.last_mut()) {
+            (TokenType::LBrace, Some(Mode::Interpolation { depth })) => *depth += 1,
+            (TokenType::RBrace, Some(Mode::Interpolation { depth: 0 })) => {
+                self.modes.pop();
+                tok.token_type = TokenType::InterpolationEnd;
+            }
+            (TokenType::RBrace, Some(Mode::Interpolation { depth })) => *depth -= 1,
+            _ => {}
+        }
+        tok
+    }
+
+    /// Reports every template string still open at the end of the input.
+    fn close_templates(&mut self) {
+        for mode in self.modes.drain(..).rev() {
+            if let Mode::Template { start } = mode {
+                self.errors.push(LexError::UnterminatedTemplate { start });
+            }
+        }
+    }
+
+    pub fn next_token(&mut self) -> Token<'src> {
+        let trivia_start = self.position;
+
+        let semicolon_after = self.semicolon_after.take();
+
+        let in_template = matches!(self.modes.last(), Some(Mode::Template { .. }));
+        let semicolon = semicolon_after.is_some_and(|line| self.at_statement_end(line));
+        if !in_template && !semicolon {
+            self.skip_whitespace();
+        }
+
+        let start = self.current_position();
+
+        let mut tok: Token<'src> = match self.ch {
+            _ if semicolon => Token::new_from_str(TokenType::Semicolon, "\n"),
+            '\0' if self.at_eof() => {
+                self.close_templates();
+                Token::new_from_str(TokenType::Eof, "\0")
+            }
+            _ if in_template => self.read_template_part(),
+            '`' => {
+                self.modes.push(Mode::Template { start });
+                self.read_char();
+                Token::new_from_str(TokenType::TemplateStart, self.slice_from(start.offset))
+            }
+            '/' if self.at_doc_comment() => {
+                let literal = &self.read_line_comment()["///".len()..];
+                Token::new_from_str(TokenType::DocComment, literal)
+            }
+            '"' if self.input_starts_with(self.position, MULTILINE_QUOTES) => {
+                let literal = self.read_multiline_string();
+                Token::new(TokenType::String, literal, Span::default())
+            }
+            '"' => {
+                let literal = self.read_string();
+                Token::new(TokenType::String, literal, Span::default())
+            }
+            'r' if self.at_raw_string() => {
+                let literal = self.read_raw_string();
+                Token::new_from_str(TokenType::String, literal)
+            }
+            _ => {
+                if is_identifier_start(self.ch) {
+                    let literal = self.read_identifier();
+                    match self.keywords.lookup(&literal) {
+                        Word::Keyword(token_type) => {
+                            Token::new(token_type, literal, Span::default())
+                        }
+                        word => {
+                            if word == Word::Reserved {
+                                self.errors.push(LexError::ReservedWord {
+                                    word: literal.to_string(),
+                                    span: Span::new(start, self.current_position()),
+                                });
+                            }
+                            let symbol = self.interner.intern(&literal);
+                            let mut tok = Token::new(TokenType::Ident, literal, Span::default());
+                            tok.symbol = Some(symbol);
+                            tok
+                        }
+                    }
+                } else if is_digit(self.ch) {
+                    let (token_type, literal) = self.read_number();
+                    Token::new_from_str(token_type, literal)
+                } else if let Some(tok) = self.read_operator() {
+                    self.track_braces(tok)
+                } else {
+                    self.unexpected_char()
+                }
+            }
+        };
+
+        tok.span = Span::new(start, self.current_position());
+        self.semicolon_after =
+            (!semicolon && ends_statement(&tok.token_type) && self.modes.is_empty())
+                .then_some(tok.span.end.line);
+
+        if self.lossless {
+            let leading = &self.input[trivia_start..start.offset];
+            let lexeme = self.slice_from(start.offset);
+
+            // Whitespace at the start of a template's text is part of it.
+            let trailing_start = self.position;
+            if !matches!(self.modes.last(), Some(Mode::Template { .. })) {
+                self.skip_trailing_trivia();
+            }
+            let trailing = self.slice_from(trailing_start);
+
+            tok.trivia = Some(Box::new(Trivia {
+                leading: Cow::Borrowed(leading),
+                lexeme: Cow::Borrowed(lexeme),
+                trailing: Cow::Borrowed(trailing),
+            }));
+        }
+
+        tok
+    }
+}
+
+impl<'src> Iterator for Lexer<'src> {
+    type Item = Token<'src>;
+
+    fn next(&mut self) -> Option<Token<'src>> {
+        let tok = self.next_token();
+        if tok.token_type == TokenType::Eof {
+            None
+        } else {
+            Some(tok)
+        }
+    }
+}
+
+const MULTILINE_QUOTES: &str = "\"\"\"";
+
+/// Returns where the closing `"""` of a multi-line string is in `rest`, the
+/// input just after the opening quotes, skipping over escaped quotes.
+fn find_multiline_end(rest: &str) -> Option<usize> {
+    // Walking chars rather than bytes means the char after a backslash is
+    // skipped whole, however many bytes it takes.
+    let bytes = rest.as_bytes();
+    let mut chars = rest.char_indices();
+    while let Some((i, ch)) = chars.next() {
+        match ch {
+            '\\' => {
+                chars.next();
+            }
+            '"' if bytes[i..].starts_with(MULTILINE_QUOTES.as_bytes()) => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Which part of a multi-line string's raw contents is its body, and how much
+/// indentation to strip from each line of it. See `read_multiline_string`.
+struct MultilineLayout<'a> {
+    body: std::ops::Range<usize>,
+    indent: &'a str,
+}
+
+impl<'a> MultilineLayout<'a> {
+    fn new(raw: &'a str) -> MultilineLayout<'a> {
+        let is_blank = |line: &str| line.chars().all(|ch| matches!(ch, ' ' | '\t'));
+
+        // A blank line right after the opening quotes isn't part of the string.
+        let mut body = 0..raw.len();
+        if let Some(line_end) = raw.find(['\n', '\r']) {
+            if is_blank(&raw[..line_end]) {
+                body.start = line_end + line_break_len(&raw[line_end..]);
+            }
         }
+
+        // Neither is the line the closing quotes are on, if there's nothing
+        // else on it, nor the line break before it.
+        let mut closing_indent = None;
+        let contents = &raw[body.clone()];
+        let last_line_start = contents.rfind(['\n', '\r']).map_or(0, |i| i + 1);
+        let last_line = &contents[last_line_start..];
+        if is_blank(last_line) && (last_line_start > 0 || body.start > 0) {
+            closing_indent = Some(last_line);
+            let mut line_break = last_line_start.saturating_sub(1);
+            if contents[line_break..].starts_with('\n') && contents[..line_break].ends_with('\r') {
+                line_break -= 1;
+            }
+            body.end = body.start + line_break;
+        }
+
+        let indents = raw[body.clone()]
+            .lines()
+            .flat_map(|line| line.split('\r'))
+            .filter(|line| !is_blank(line))
+            .map(|line| &line[..line.len() - line.trim_start_matches([' ', '\t']).len()])
+            .chain(closing_indent);
+        let indent = indents
+            .reduce(|common, indent| {
+                let len = common
+                    .bytes()
+                    .zip(indent.bytes())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                &common[..len]
+            })
+            .unwrap_or("");
+
+        MultilineLayout { body, indent }
     }
+}
+
+fn line_break_len(rest: &str) -> usize {
+    if rest.starts_with("\r\n") {
+        2
+    } else {
+        1
+    }
+}
+
+/// Every operator and delimiter. The lexer picks the longest one that matches,
+/// so the order here doesn't matter and `<=` is never read as `<` then `=`.
+const OPERATORS: &[(&str, TokenType)] = &[
+    ("=", TokenType::Assign),
+    ("+", TokenType::Plus),
+    ("-", TokenType::Minus),
+    ("!", TokenType::Bang),
+    ("*", TokenType::Asterisk),
+    ("/", TokenType::Slash),
+    ("%", TokenType::Percent),
+    ("<", TokenType::Lt),
+    (">", TokenType::Gt),
+    ("==", TokenType::Eq),
+    ("!=", TokenType::NotEq),
+    ("<=", TokenType::LtEq),
+    (">=", TokenType::GtEq),
+    (",", TokenType::Comma),
+    (";", TokenType::Semicolon),
+    ("(", TokenType::LParen),
+    (")", TokenType::RParen),
+    ("{", TokenType::LBrace),
+    ("}", TokenType::RBrace),
+];
 
-    pub fn next_token(&mut self) -> Token {
-        self.skip_whitespace();
+/// Tokens that a line break after ends a statement with, as in Go: the ones
+/// that can come last in an expression, and `return` on its own.
+pub fn ends_statement(token_type: &TokenType) -> bool {
+    matches!(
+        token_type,
+        TokenType::Ident
+            | TokenType::Int
+            | TokenType::Float
+            | TokenType::String
+            | TokenType::True
+            | TokenType::False
+            | TokenType::Illegal
+            | TokenType::TemplateEnd
+            | TokenType::RParen
+            | TokenType::RBrace
+            | TokenType::Return
+    )
+}
+
+/// Identifiers follow Unicode's XID_Start / XID_Continue properties (the same
+/// rules Rust uses), with `_` additionally allowed as the first char.
+fn is_identifier_start(ch: char) -> bool {
+    ch == '_' || ch.is_xid_start()
+}
+
+fn is_identifier_continue(ch: char) -> bool {
+    ch.is_xid_continue()
+}
+
+fn is_digit(ch: char) -> bool {
+    ch.is_ascii_digit()
+}
+
+/// Whether `spelling` can be read as an operator without changing how anything
+/// else is lexed: it isn't empty, has no whitespace or chars that could be part
+/// of an identifier or number, no quotes or backticks, and no `//` or `/*`,
+/// including one it would make by ending in a `/`.
+pub(crate) fn is_operator_spelling(spelling: &str) -> bool {
+    !spelling.is_empty()
+        && !spelling.chars().any(|ch| {
+            ch.is_whitespace() || is_identifier_continue(ch) || matches!(ch, '"' | '\'' | '`')
+        })
+        && !spelling.contains("//")
+        && !spelling.contains("/*")
+        && !spelling.ends_with('/')
+}
 
-        let tok: Token = match self.ch as char {
-            '=' => {
-                // check for '=='
-                if self.peek_char() == 61 {
-                    let ch = self.ch as char;
-                    self.read_char();
-                    Token::new_from_str(TokenType::Eq, &format!("{}{}", ch, self.ch as char))
-                } else {
-                    Token::new_from_byte(TokenType::Assign, self.ch)
-                }
-            }
-            '!' => {
-                // check for '!='
-                if self.peek_char() == 61 {
-                    let ch = self.ch as char;
-                    self.read_char();
-                    Token::new_from_str(TokenType::NotEq, &format!("{}{}", ch, self.ch as char))
-                } else {
-                    Token::new_from_byte(TokenType::Bang, self.ch)
-                }
-            }
-            ';' => Token::new_from_byte(TokenType::Semicolon, self.ch),
-            '(' => Token::new_from_byte(TokenType::LParen, self.ch),
-            ')' => Token::new_from_byte(TokenType::RParen, self.ch),
-            ',' => Token::new_from_byte(TokenType::Comma, self.ch),
-            '+' => Token::new_from_byte(TokenType::Plus, self.ch),
-            '{' => Token::new_from_byte(TokenType::LBrace, self.ch),
-            '}' => Token::new_from_byte(TokenType::RBrace, self.ch),
-            '-' => Token::new_from_byte(TokenType::Minus, self.ch),
-            '/' => Token::new_from_byte(TokenType::Slash, self.ch),
-            '*' => Token::new_from_byte(TokenType::Asterisk, self.ch),
-            '<' => Token::new_from_byte(TokenType::Lt, self.ch),
-            '>' => Token::new_from_byte(TokenType::Gt, self.ch),
-            '\0' => Token::new_from_byte(TokenType::Eof, 0),
-            _ => {
-                if is_letter(self.ch) {
-                    let literal = self.read_identifier();
-                    return Token::new_from_str(Token::lookup_ident(literal), literal);
-                } else if is_digit(self.ch) {
-                    let literal = self.read_number();
-                    return Token::new_from_str(TokenType::Int, literal);
-                } else {
-                    return Token::new_from_byte(TokenType::Illegal, self.ch);
-                }
-            }
-        };
+/// Checks the full text of a number literal, returning whether it is an `Int`
+/// or a `Float`, or why it is malformed.
+///
+/// Ints can be decimal or use a `0x`, `0o` or `0b` prefix. Floats are decimal
+/// only and need a fractional part, an exponent or both. `_` can be used to
+/// separate digits, but only between two digits.
+fn classify_number(literal: &str) -> Result<TokenType, &'static str> {
+    let radix = match literal.get(..2) {
+        Some("0x" | "0X") => 16,
+        Some("0o" | "0O") => 8,
+        Some("0b" | "0B") => 2,
+        _ => 10,
+    };
+    if radix != 10 {
+        check_digits(&literal[2..], radix)?;
+        return Ok(TokenType::Int);
+    }
 
-        self.read_char();
+    let (mantissa, exponent) = match literal.find(['e', 'E']) {
+        Some(i) => (&literal[..i], Some(&literal[i + 1..])),
+        None => (literal, None),
+    };
+    let (whole, fraction) = match mantissa.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (mantissa, None),
+    };
 
-        tok
+    check_digits(whole, 10)?;
+    if let Some(fraction) = fraction {
+        check_digits(fraction, 10)?;
+    }
+    if let Some(exponent) = exponent {
+        let digits = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        check_digits(digits, 10).map_err(|_| "exponent has no digits")?;
     }
-}
 
-fn is_letter(ch: u8) -> bool {
-    97 <= ch && ch <= 122 || // lowercase a-z
-    65 <= ch && ch <= 90 || // uppercase A-Z
-    ch == 95 // underscore
+    if fraction.is_some() || exponent.is_some() {
+        Ok(TokenType::Float)
+    } else {
+        Ok(TokenType::Int)
+    }
 }
 
-fn is_digit(ch: u8) -> bool {
-    48 <= ch && ch <= 57 // 0 to 9
+fn check_digits(digits: &str, radix: u32) -> Result<(), &'static str> {
+    if digits.is_empty() {
+        return Err("expected digits");
+    }
+    if let Some(ch) = digits.chars().find(|ch| *ch != '_' && !ch.is_digit(radix)) {
+        return Err(if ch.is_ascii_digit() {
+            "digit out of range for the number's base"
+        } else {
+            "unexpected character in number"
+        });
+    }
+    if digits.starts_with('_') || digits.ends_with('_') {
+        return Err("`_` must be between two digits");
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::lexer::Lexer;
+    use crate::keywords::KeywordTable;
+    use crate::lexer::{LexError, Lexer};
+    use crate::operators::{
+        Associativity, InfixRule, OperatorTable, PrefixRule, EQUALS, PREFIX, SUM,
+    };
     use crate::token::*;
+    use std::borrow::Cow;
+    use std::sync::Arc;
 
-    #[test]
-    fn test_next_token() {
-        let input = r#"let five = 5;
+    fn span(start: (usize, usize, usize), end: (usize, usize, usize)) -> Span {
+        Span::new(
+            Position::new(start.0, start.1, start.2),
+            Position::new(end.0, end.1, end.2),
+        )
+    }
+
+    const NEXT_TOKEN_INPUT: &str = r#"let five = 5;
 let ten = 10;
 
 let add = fn(x, y) {
@@ -157,7 +1270,7 @@ let add = fn(x, y) {
 };
 
 let result = add(five, ten);
-!-/*5;
+!-/ *5;
 5 < 10 > 5;
 
 if (5 < 10) {
@@ -168,8 +1281,15 @@ if (5 < 10) {
 
 10 == 10;
 10 != 9;
+10 <= 9 >= 8 % 7;
+"foobar"
+"foo bar"
 "#;
 
+    #[test]
+    fn test_next_token() {
+        let input = NEXT_TOKEN_INPUT;
+
         let tests: Vec<Token> = vec![
             Token::new_from_str(TokenType::Let, "let"),
             Token::new_from_str(TokenType::Ident, "five"),
@@ -236,6 +1356,7 @@ if (5 < 10) {
             Token::new_from_str(TokenType::False, "false"),
             Token::new_from_str(TokenType::Semicolon, ";"),
             Token::new_from_str(TokenType::RBrace, "}"),
+            Token::new_from_str(TokenType::Semicolon, "\n"),
             Token::new_from_str(TokenType::Int, "10"),
             Token::new_from_str(TokenType::Eq, "=="),
             Token::new_from_str(TokenType::Int, "10"),
@@ -244,10 +1365,22 @@ if (5 < 10) {
             Token::new_from_str(TokenType::NotEq, "!="),
             Token::new_from_str(TokenType::Int, "9"),
             Token::new_from_str(TokenType::Semicolon, ";"),
+            Token::new_from_str(TokenType::Int, "10"),
+            Token::new_from_str(TokenType::LtEq, "<="),
+            Token::new_from_str(TokenType::Int, "9"),
+            Token::new_from_str(TokenType::GtEq, ">="),
+            Token::new_from_str(TokenType::Int, "8"),
+            Token::new_from_str(TokenType::Percent, "%"),
+            Token::new_from_str(TokenType::Int, "7"),
+            Token::new_from_str(TokenType::Semicolon, ";"),
+            Token::new_from_str(TokenType::String, "foobar"),
+            Token::new_from_str(TokenType::Semicolon, "\n"),
+            Token::new_from_str(TokenType::String, "foo bar"),
+            Token::new_from_str(TokenType::Semicolon, "\n"),
             Token::new_from_str(TokenType::Eof, "\0"),
         ];
 
-        let mut l = Lexer::new(input.to_string());
+        let mut l = Lexer::new(input);
 
         for expected_token in tests {
             let tok: Token = l.next_token();
@@ -274,4 +1407,671 @@ if (5 < 10) {
             // );
         }
     }
+
+    #[test]
+    fn test_unicode_identifiers() {
+        // The second `café` spells the accent as `e` + U+0301 COMBINING ACUTE ACCENT.
+        let input = "let café = 5;\nlet 变量 = cafe\u{301} * _ñ2;\n5 € 🦀 5;";
+
+        let tests: Vec<Token> = vec![
+            Token::new_from_str(TokenType::Let, "let"),
+            Token::new_from_str(TokenType::Ident, "café"),
+            Token::new_from_str(TokenType::Assign, "="),
+            Token::new_from_str(TokenType::Int, "5"),
+            Token::new_from_str(TokenType::Semicolon, ";"),
+            Token::new_from_str(TokenType::Let, "let"),
+            Token::new_from_str(TokenType::Ident, "变量"),
+            Token::new_from_str(TokenType::Assign, "="),
+            Token::new_from_str(TokenType::Ident, "café"),
+            Token::new_from_str(TokenType::Asterisk, "*"),
+            Token::new_from_str(TokenType::Ident, "_ñ2"),
+            Token::new_from_str(TokenType::Semicolon, ";"),
+            Token::new_from_str(TokenType::Int, "5"),
+            Token::new_from_str(TokenType::Illegal, "€"),
+            Token::new_from_str(TokenType::Illegal, "🦀"),
+            Token::new_from_str(TokenType::Int, "5"),
+            Token::new_from_str(TokenType::Semicolon, ";"),
+            Token::new_from_str(TokenType::Eof, "\0"),
+        ];
+
+        let mut l = Lexer::new(input);
+
+        for expected_token in tests {
+            let tok: Token = l.next_token();
+
+            assert_eq!(
+                expected_token.token_type, tok.token_type,
+                "token_type wrong, expected {:?}, got {:?}",
+                expected_token.token_type, tok.token_type
+            );
+            assert_eq!(
+                expected_token.literal, tok.literal,
+                "literal wrong, expected {:?}, got {:?}",
+                expected_token.literal, tok.literal
+            );
+        }
+
+        let errors: Vec<String> = l.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "3:3: Unexpected character '€'",
+                "3:5: Unexpected character '🦀'"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_token_spans() {
+        // Tabs are a single column, `\r\n` is one line break, and `é` is two
+        // bytes but one column.
+        let input = "let\tx = 10;\r\né != 5\n\n";
+
+        let tests: Vec<(TokenType, Span)> = vec![
+            (TokenType::Let, span((0, 1, 1), (3, 1, 4))),
+            (TokenType::Ident, span((4, 1, 5), (5, 1, 6))),
+            (TokenType::Assign, span((6, 1, 7), (7, 1, 8))),
+            (TokenType::Int, span((8, 1, 9), (10, 1, 11))),
+            (TokenType::Semicolon, span((10, 1, 11), (11, 1, 12))),
+            (TokenType::Ident, span((13, 2, 1), (15, 2, 2))),
+            (TokenType::NotEq, span((16, 2, 3), (18, 2, 5))),
+            (TokenType::Int, span((19, 2, 6), (20, 2, 7))),
+            (TokenType::Semicolon, span((20, 2, 7), (20, 2, 7))),
+            (TokenType::Eof, span((22, 4, 1), (22, 4, 1))),
+            (TokenType::Eof, span((22, 4, 1), (22, 4, 1))),
+        ];
+
+        let mut l = Lexer::new(input);
+
+        for (expected_type, expected_span) in tests {
+            let tok = l.next_token();
+
+            assert_eq!(
+                expected_type, tok.token_type,
+                "token_type wrong, expected {:?}, got {:?}",
+                expected_type, tok.token_type
+            );
+            assert_eq!(
+                expected_span, tok.span,
+                "span wrong for {:?}, expected {:?}, got {:?}",
+                tok.literal, expected_span, tok.span
+            );
+        }
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let input = r#""a\tb\n" "\"quoted\" \\ \u{1F980} é" "bad \q and \u{110000}" "unterminated"#;
+
+        let mut l = Lexer::new(input);
+
+        let expected = vec!["a\tb\n", "\"quoted\" \\ 🦀 é", "bad  and ", "unterminated"];
+        for literal in expected {
+            let tok = l.next_token();
+            assert_eq!(tok.token_type, TokenType::String);
+            assert_eq!(tok.literal, literal);
+        }
+        assert_eq!(l.next_token().token_type, TokenType::Eof);
+
+        let errors: Vec<String> = l.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "1:43: Invalid escape sequence \\q",
+                "1:50: Invalid escape sequence \\u{110000}",
+                "1:62: Unterminated string literal",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_raw_strings() {
+        let input = r###"r"C:\path\n" r#"say "hi""# r##"a "# b"## r"" r #"###;
+
+        let mut l = Lexer::new(input);
+        let expected = vec![r"C:\path\n", r#"say "hi""#, r##"a "# b"##, ""];
+        for literal in expected {
+            let tok = l.next_token();
+            assert_eq!(tok.token_type, TokenType::String);
+            assert_eq!(tok.literal, literal);
+            assert!(matches!(tok.literal, Cow::Borrowed(_)));
+        }
+        // Without a quote, `r` is an identifier and `#` isn't anything.
+        assert_eq!(l.next_token().token_type, TokenType::Ident);
+        assert_eq!(l.next_token().token_type, TokenType::Illegal);
+        assert_eq!(l.next_token().token_type, TokenType::Eof);
+
+        let mut l = Lexer::new("let s = r##\"never \"# closed");
+        let tokens: Vec<_> = l.by_ref().collect();
+        assert_eq!(tokens.last().unwrap().literal, "never \"# closed");
+        let errors: Vec<String> = l.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec!["1:9: Unterminated raw string, expected a closing \"##"]
+        );
+    }
+
+    #[test]
+    fn test_multiline_strings() {
+        let tests = vec![
+            // Indentation common to every line is stripped, including the
+            // closing quotes'.
+            (
+                "\"\"\"\n    SELECT *\n      FROM t\n    \"\"\"",
+                "SELECT *\n  FROM t",
+            ),
+            (
+                "\"\"\"\n    SELECT *\n      FROM t\n  \"\"\"",
+                "  SELECT *\n    FROM t",
+            ),
+            // Closing quotes at the end of the last line keep that line.
+            ("\"\"\"\n  a\n    b\"\"\"", "a\n  b"),
+            // Blank lines don't count towards the indentation, and \r\n
+            // becomes \n.
+            ("\"\"\"\r\n\ta\r\n\r\n\t\tb\r\n\t\"\"\"", "a\n\n\tb"),
+            // Escapes still work, even for quotes.
+            ("\"\"\"\n  \\\"\"\" \\t \\u{e9}\n  \"\"\"", "\"\"\" \t é"),
+            ("\"\"\"one line\"\"\"", "one line"),
+            ("\"\"\"\n\"\"\"", ""),
+            ("\"\"\"\"\"\"", ""),
+            // Non-ASCII text, including right after an escape
+            ("\"\"\"é\"\"\"", "é"),
+            ("\"\"\"\n  grüße 😀\n  \"\"\"", "grüße 😀"),
+            ("\"\"\"\\\\é\\\"\"\"\"", "\\é\""),
+        ];
+
+        for (input, expected) in tests {
+            let mut l = Lexer::new(input);
+            let tok = l.next_token();
+            assert_eq!(tok.token_type, TokenType::String, "input {:?}", input);
+            assert_eq!(tok.literal, expected, "input {:?}", input);
+            assert_eq!(tok.span.end.offset, input.len(), "input {:?}", input);
+            assert_eq!(l.next_token().token_type, TokenType::Eof);
+            assert!(l.errors().is_empty(), "input {:?}", input);
+        }
+
+        let mut l = Lexer::new("x = 1;\nlet s = \"\"\"\n  open \\q\n\" \"\" ;");
+        let tokens: Vec<_> = l.by_ref().collect();
+        assert_eq!(tokens.len(), 8);
+        let errors: Vec<String> = l.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec!["2:9: Unterminated multi-line string, expected a closing \"\"\""]
+        );
+
+        // An escape of a char that takes more than one byte, and an
+        // unterminated string with non-ASCII text in it.
+        for (input, expected) in [
+            ("\"\"\"\\é\"\"\"", "1:4: Invalid escape sequence \\é"),
+            (
+                "x\"\"\"é \\😀",
+                "1:2: Unterminated multi-line string, expected a closing \"\"\"",
+            ),
+        ] {
+            let mut l = Lexer::new(input);
+            let tokens: Vec<_> = l.by_ref().map(|tok| tok.token_type).collect();
+            assert!(tokens.contains(&TokenType::String), "input {:?}", input);
+            let errors: Vec<String> = l.errors().iter().map(|e| e.to_string()).collect();
+            assert_eq!(errors[0], expected, "input {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_template_strings() {
+        let input = r#"`Hi ${name}! ${ `${a}` + { 1 } } \${x} \` `; `$ {`"#;
+
+        let expected = vec![
+            (TokenType::TemplateStart, "`"),
+            (TokenType::TemplateText, "Hi "),
+            (TokenType::InterpolationStart, "${"),
+            (TokenType::Ident, "name"),
+            (TokenType::InterpolationEnd, "}"),
+            (TokenType::TemplateText, "! "),
+            (TokenType::InterpolationStart, "${"),
+            (TokenType::TemplateStart, "`"),
+            (TokenType::InterpolationStart, "${"),
+            (TokenType::Ident, "a"),
+            (TokenType::InterpolationEnd, "}"),
+            (TokenType::TemplateEnd, "`"),
+            (TokenType::Plus, "+"),
+            (TokenType::LBrace, "{"),
+            (TokenType::Int, "1"),
+            (TokenType::RBrace, "}"),
+            (TokenType::InterpolationEnd, "}"),
+            (TokenType::TemplateText, " ${x} ` "),
+            (TokenType::TemplateEnd, "`"),
+            (TokenType::Semicolon, ";"),
+            (TokenType::TemplateStart, "`"),
+            (TokenType::TemplateText, "$ {"),
+            (TokenType::TemplateEnd, "`"),
+            (TokenType::Eof, "\0"),
+        ];
+
+        let mut l = Lexer::new(input);
+        for (i, (token_type, literal)) in expected.into_iter().enumerate() {
+            let tok = l.next_token();
+            assert_eq!(tok.token_type, token_type, "tests[{}] - wrong type", i);
+            assert_eq!(tok.literal, literal, "tests[{}] - wrong literal", i);
+        }
+        assert!(l.errors().is_empty());
+        assert!(l.modes().is_empty());
+    }
+
+    #[test]
+    fn test_unterminated_templates() {
+        let mut l = Lexer::new("let s = `a ${ `b");
+        let types: Vec<_> = l.by_ref().map(|tok| tok.token_type).collect();
+        assert_eq!(
+            types,
+            vec![
+                TokenType::Let,
+                TokenType::Ident,
+                TokenType::Assign,
+                TokenType::TemplateStart,
+                TokenType::TemplateText,
+                TokenType::InterpolationStart,
+                TokenType::TemplateStart,
+                TokenType::TemplateText,
+            ]
+        );
+
+        let errors: Vec<String> = l.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "1:15: Unterminated template string",
+                "1:9: Unterminated template string",
+            ]
+        );
+        assert!(l.modes().is_empty());
+    }
+
+    #[test]
+    fn test_automatic_semicolons() {
+        let input = "let x = a +
+    b
+let y = f(x) // comment
+return
+}
+`t` /* a comment
+over lines */ 1.5 \"s\" true
+let z = -
+    x
+{ `a ${ b
+} c` }
+a /// doc
+- b
+return /// doc
+x @
+";
+
+        let expected = vec![
+            "let", "x", "=", "a", "+", "b", "\n", //
+            "let", "y", "=", "f", "(", "x", ")", "\n", //
+            "return", "\n", //
+            "}", "\n", //
+            "`", "t", "`", "\n", //
+            "1.5", "s", "true", "\n", //
+            "let", "z", "=", "-", "x", "\n", //
+            "{", "`", "a ", "${", "b", "}", " c", "`", "}", "\n", //
+            "a", "\n", " doc", "-", "b", "\n", //
+            "return", "\n", " doc", //
+            "x", "@", "\n", //
+        ];
+
+        let mut l = Lexer::new(input);
+        let tokens: Vec<_> = l.by_ref().collect();
+        let literals: Vec<_> = tokens.iter().map(|tok| tok.literal.as_ref()).collect();
+        assert_eq!(literals, expected);
+
+        for tok in tokens.iter().filter(|tok| tok.literal == "\n") {
+            assert_eq!(tok.token_type, TokenType::Semicolon);
+            assert_eq!(tok.span.start, tok.span.end);
+        }
+        assert_eq!(l.errors().len(), 1);
+    }
+
+    #[test]
+    fn test_comments() {
+        let input = r#"// a line comment
+let x = 10 / 2; // trailing comment
+/* a block /* with a nested */ comment */ x
+//// not a doc comment
+/// Doubles a number.
+///Second line
+let double = x;
+/* never closed /* */"#;
+
+        let tests: Vec<Token> = vec![
+            Token::new_from_str(TokenType::Let, "let"),
+            Token::new_from_str(TokenType::Ident, "x"),
+            Token::new_from_str(TokenType::Assign, "="),
+            Token::new_from_str(TokenType::Int, "10"),
+            Token::new_from_str(TokenType::Slash, "/"),
+            Token::new_from_str(TokenType::Int, "2"),
+            Token::new_from_str(TokenType::Semicolon, ";"),
+            Token::new_from_str(TokenType::Ident, "x"),
+            Token::new_from_str(TokenType::Semicolon, "\n"),
+            Token::new_from_str(TokenType::DocComment, " Doubles a number."),
+            Token::new_from_str(TokenType::DocComment, "Second line"),
+            Token::new_from_str(TokenType::Let, "let"),
+            Token::new_from_str(TokenType::Ident, "double"),
+            Token::new_from_str(TokenType::Assign, "="),
+            Token::new_from_str(TokenType::Ident, "x"),
+            Token::new_from_str(TokenType::Semicolon, ";"),
+            Token::new_from_str(TokenType::Eof, "\0"),
+        ];
+
+        let mut l = Lexer::new(input);
+
+        for expected_token in tests {
+            let tok: Token = l.next_token();
+
+            assert_eq!(
+                expected_token.token_type, tok.token_type,
+                "token_type wrong, expected {:?}, got {:?}",
+                expected_token.token_type, tok.token_type
+            );
+            assert_eq!(
+                expected_token.literal, tok.literal,
+                "literal wrong, expected {:?}, got {:?}",
+                expected_token.literal, tok.literal
+            );
+        }
+
+        let errors: Vec<String> = l.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(errors, vec!["8:1: Unterminated block comment"]);
+    }
+
+    #[test]
+    fn test_numbers() {
+        let input =
+            "0xFF 0o17 0b1010 1_000_000 3.14 1e-9 2.5E+3 0xe-1 7.method 0x 0b102 1_ 12abc 1e";
+
+        let tests: Vec<Token> = vec![
+            Token::new_from_str(TokenType::Int, "0xFF"),
+            Token::new_from_str(TokenType::Int, "0o17"),
+            Token::new_from_str(TokenType::Int, "0b1010"),
+            Token::new_from_str(TokenType::Int, "1_000_000"),
+            Token::new_from_str(TokenType::Float, "3.14"),
+            Token::new_from_str(TokenType::Float, "1e-9"),
+            Token::new_from_str(TokenType::Float, "2.5E+3"),
+            Token::new_from_str(TokenType::Int, "0xe"),
+            Token::new_from_str(TokenType::Minus, "-"),
+            Token::new_from_str(TokenType::Int, "1"),
+            Token::new_from_str(TokenType::Int, "7"),
+            Token::new_from_str(TokenType::Illegal, "."),
+            Token::new_from_str(TokenType::Ident, "method"),
+            Token::new_from_str(TokenType::Illegal, "0x"),
+            Token::new_from_str(TokenType::Illegal, "0b102"),
+            Token::new_from_str(TokenType::Illegal, "1_"),
+            Token::new_from_str(TokenType::Illegal, "12abc"),
+            Token::new_from_str(TokenType::Illegal, "1e"),
+            Token::new_from_str(TokenType::Eof, "\0"),
+        ];
+
+        let mut l = Lexer::new(input);
+
+        for expected_token in tests {
+            let tok: Token = l.next_token();
+
+            assert_eq!(
+                expected_token.token_type, tok.token_type,
+                "token_type wrong for {:?}, expected {:?}, got {:?}",
+                tok.literal, expected_token.token_type, tok.token_type
+            );
+            assert_eq!(
+                expected_token.literal, tok.literal,
+                "literal wrong, expected {:?}, got {:?}",
+                expected_token.literal, tok.literal
+            );
+        }
+
+        let errors: Vec<String> = l.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "1:52: Unexpected character '.'",
+                "1:60: Malformed number 0x: expected digits",
+                "1:63: Malformed number 0b102: digit out of range for the number's base",
+                "1:69: Malformed number 1_: `_` must be between two digits",
+                "1:72: Malformed number 12abc: unexpected character in number",
+                "1:78: Malformed number 1e: exponent has no digits",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_identifier_symbols() {
+        let mut l = Lexer::new("let x = x + café; fn");
+        let symbols: Vec<_> = l.by_ref().map(|tok| tok.symbol).collect();
+        let interner = l.interner();
+
+        let x = interner.get("x");
+        let cafe = interner.get("café");
+        assert!(x.is_some() && cafe.is_some() && x != cafe);
+        assert_eq!(symbols, vec![None, x, None, x, None, cafe, None, None]);
+        assert_eq!(interner.len(), 2);
+    }
+
+    #[test]
+    fn test_keyword_tables() {
+        let input = "funcion si sino fn while x";
+        let types = |keywords: KeywordTable| {
+            let mut l = Lexer::new(input).with_keywords(Arc::new(keywords));
+            let types: Vec<_> = l.by_ref().map(|tok| tok.token_type).collect();
+            let errors: Vec<_> = l.errors().iter().map(|e| e.to_string()).collect();
+            (types, errors)
+        };
+        use TokenType::*;
+
+        let (default, errors) = types(KeywordTable::default());
+        assert_eq!(default, vec![Ident, Ident, Ident, Function, Ident, Ident]);
+        assert!(errors.is_empty());
+
+        let (spanish, _) = types(KeywordTable::dialect("es").unwrap());
+        assert_eq!(spanish, vec![Function, If, Else, Function, Ident, Ident]);
+
+        let (strict, errors) = types(KeywordTable::dialect("strict").unwrap());
+        assert_eq!(strict, default);
+        assert_eq!(errors, vec!["1:20: 'while' is a reserved word"]);
+
+        let l = Lexer::new("x while")
+            .with_keywords(Arc::new(KeywordTable::empty().with_reserved("while")));
+        let symbols: Vec<_> = l.map(|tok| tok.symbol.is_some()).collect();
+        assert_eq!(symbols, vec![true, true]);
+    }
+
+    #[test]
+    fn test_operator_tables() {
+        let input = "a <=> b <= @c + d";
+        let types = |operators: OperatorTable| {
+            let mut l = Lexer::new(input).with_operators(Arc::new(operators));
+            let types: Vec<_> = l.by_ref().map(|tok| tok.token_type).collect();
+            (types, l.errors().len())
+        };
+        use TokenType::*;
+
+        let (default, errors) = types(OperatorTable::default());
+        assert_eq!(
+            default,
+            vec![Ident, LtEq, Gt, Ident, LtEq, Illegal, Ident, Plus, Ident]
+        );
+        assert_eq!(errors, 1);
+
+        // `+` is already an operator, so it stays a `Plus`
+        let operators = OperatorTable::default()
+            .with_infix(
+                Operator("<=>".into()),
+                InfixRule::binary(EQUALS, Associativity::Left),
+            )
+            .with_infix(
+                Operator("+".into()),
+                InfixRule::binary(SUM, Associativity::Right),
+            )
+            .with_prefix(Operator("@".into()), PrefixRule::unary(PREFIX));
+        let (custom, errors) = types(operators);
+        assert_eq!(
+            custom,
+            vec![
+                Ident,
+                Operator("<=>".into()),
+                Ident,
+                LtEq,
+                Operator("@".into()),
+                Ident,
+                Plus,
+                Ident
+            ]
+        );
+        assert_eq!(errors, 0);
+    }
+
+    #[test]
+    fn test_iterator_borrows_literals() {
+        let input = "let s = \"plain\"; let t = \"esc\\n\"; cafe\u{301}";
+
+        let tokens: Vec<Token> = Lexer::new(input).collect();
+
+        assert_eq!(tokens.len(), 11, "wrong number of tokens: {:?}", tokens);
+        assert!(
+            tokens.iter().all(|t| t.token_type != TokenType::Eof),
+            "iterator shouldn't yield Eof"
+        );
+
+        // Only the string with an escape and the identifier that had to be
+        // normalized need their own copy of the text.
+        let owned: Vec<&str> = tokens
+            .iter()
+            .filter(|t| matches!(t.literal, Cow::Owned(_)))
+            .map(|t| t.literal.as_ref())
+            .collect();
+        assert_eq!(owned, vec!["esc\n", "café"]);
+    }
+
+    #[test]
+    fn test_unexpected_characters() {
+        let input = "let x = 5 @ 3;\n#$\u{7};";
+
+        let tests: Vec<Token> = vec![
+            Token::new_from_str(TokenType::Let, "let"),
+            Token::new_from_str(TokenType::Ident, "x"),
+            Token::new_from_str(TokenType::Assign, "="),
+            Token::new_from_str(TokenType::Int, "5"),
+            Token::new_from_str(TokenType::Illegal, "@"),
+            Token::new_from_str(TokenType::Int, "3"),
+            Token::new_from_str(TokenType::Semicolon, ";"),
+            Token::new_from_str(TokenType::Illegal, "#"),
+            Token::new_from_str(TokenType::Illegal, "$"),
+            Token::new_from_str(TokenType::Illegal, "\u{7}"),
+            Token::new_from_str(TokenType::Semicolon, ";"),
+            Token::new_from_str(TokenType::Eof, "\0"),
+        ];
+
+        let mut l = Lexer::new(input);
+
+        for expected_token in tests {
+            let tok: Token = l.next_token();
+
+            assert_eq!(
+                expected_token.token_type, tok.token_type,
+                "token_type wrong, expected {:?}, got {:?}",
+                expected_token.token_type, tok.token_type
+            );
+            assert_eq!(
+                expected_token.literal, tok.literal,
+                "literal wrong, expected {:?}, got {:?}",
+                expected_token.literal, tok.literal
+            );
+        }
+
+        assert_eq!(
+            l.errors(),
+            &[
+                LexError::UnexpectedCharacter {
+                    ch: '@',
+                    span: span((10, 1, 11), (11, 1, 12)),
+                },
+                LexError::UnexpectedCharacter {
+                    ch: '#',
+                    span: span((15, 2, 1), (16, 2, 2)),
+                },
+                LexError::UnexpectedCharacter {
+                    ch: '$',
+                    span: span((16, 2, 2), (17, 2, 3)),
+                },
+                LexError::UnexpectedCharacter {
+                    ch: '\u{7}',
+                    span: span((17, 2, 3), (18, 2, 4)),
+                },
+            ]
+        );
+        assert_eq!(
+            l.errors()[3].to_string(),
+            "2:3: Unexpected character '\\u{7}'"
+        );
+    }
+
+    fn round_trip(input: &str) -> String {
+        let mut l = Lexer::new_lossless(input);
+        let mut out = String::new();
+
+        loop {
+            let tok = l.next_token();
+            let trivia = tok.trivia.expect("lossless token without trivia");
+            out.push_str(&trivia.leading);
+            out.push_str(&trivia.lexeme);
+            out.push_str(&trivia.trailing);
+
+            if tok.token_type == TokenType::Eof {
+                return out;
+            }
+        }
+    }
+
+    #[test]
+    fn test_lossless_round_trip() {
+        let inputs = vec![
+            NEXT_TOKEN_INPUT,
+            "",
+            "   \n\t",
+            "let x = 5; // five\r\n/* a /* nested */ block */ x\n",
+            "/// Docs.\nlet café = \"esc\\n\" @ 0b102;   \n\n  /* unterminated",
+        ];
+
+        for input in inputs {
+            assert_eq!(round_trip(input), input);
+        }
+    }
+
+    #[test]
+    fn test_lossless_trivia() {
+        let input = "  let x = 5; // five\n\n/* c */ x";
+
+        let expected = vec![
+            ("  ", "let", " "),
+            ("", "x", " "),
+            ("", "=", " "),
+            ("", "5", ""),
+            ("", ";", " // five"),
+            ("\n\n/* c */ ", "x", ""),
+            ("", "", ""),
+        ];
+
+        let mut l = Lexer::new_lossless(input);
+        for (leading, lexeme, trailing) in expected {
+            let tok = l.next_token();
+            let trivia = tok.trivia.expect("lossless token without trivia");
+            assert_eq!(
+                (
+                    trivia.leading.as_ref(),
+                    trivia.lexeme.as_ref(),
+                    trivia.trailing.as_ref()
+                ),
+                (leading, lexeme, trailing),
+                "trivia wrong for {:?}",
+                tok.token_type
+            );
+        }
+    }
 }
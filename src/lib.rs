@@ -0,0 +1,78 @@
+//! A lexer and parser for the Monkey programming language.
+//!
+//! Most users only need [`parse`], which turns source text into a
+//! [`Program`] or the [`Diagnostic`]s explaining why it couldn't:
+//!
+//! ```
+//! use rusty_monkey::ast::Node;
+//!
+//! let program = rusty_monkey::parse("let x = 1 + 2 * 3;").unwrap();
+//! assert_eq!(program.string(), "let x = (1 + (2 * 3));");
+//!
+//! let errors = rusty_monkey::parse("let = 5;").unwrap_err();
+//! assert_eq!(errors[0].to_string(), "1:5: Expected an identifier, found `=`");
+//! ```
+//!
+//! The modules underneath give more control: a [`lexer::Lexer`] can be set up
+//! with another [`keywords::KeywordTable`] or with custom operators in an
+//! [`operators::OperatorTable`] before handing it to a [`parser::Parser`], and
+//! a `Parser` still hands back a `Program` for source with errors in it, with
+//! `Error` nodes where it had to skip over something. [`incremental`] and
+//! [`stream`] lex source that is being edited or is too big to read in one go.
+
+pub mod ast;
+pub mod diagnostic;
+pub mod incremental;
+pub mod keywords;
+pub mod lexer;
+pub mod operators;
+pub mod parser;
+pub mod stream;
+pub mod symbol;
+pub mod token;
+
+pub use ast::Program;
+pub use diagnostic::Diagnostic;
+
+/// Parses `source` into a `Program`, using the default keywords.
+///
+/// Returns every problem found, lexer errors first, if there are any. Use a
+/// `parser::Parser` directly to get a partial `Program` back as well.
+pub fn parse(source: &str) -> Result<Program<'_>, Vec<Diagnostic>> {
+    let mut p = parser::Parser::new(lexer::Lexer::new(source));
+    let program = p.parse_program();
+
+    let errors = p.errors();
+    if errors.is_empty() {
+        Ok(program)
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::ast::Node;
+
+    #[test]
+    fn test_parse() {
+        let program = parse("let add = fn(a, b) { a + b }\nadd(1, 2)").unwrap();
+        assert_eq!(program.statements.len(), 2);
+        assert_eq!(program.string(), "let add = fn(a, b) { (a + b) };add(1, 2)");
+
+        let errors = parse("let x = @;\nlet = 1;").unwrap_err();
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "1:9: Unexpected character '@'",
+                "2:5: Expected an identifier, found `=`",
+            ]
+        );
+
+        let errors = parse("5 @ 3;").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "1:3: Unexpected character '@'");
+    }
+}
@@ -1,9 +1,36 @@
-mod ast;
-mod lexer;
-mod parser;
 mod repl;
-mod token;
+
+use rusty_monkey::keywords::{KeywordTable, DIALECTS};
+use std::{env, process, sync::Arc};
+
+const USAGE: &str = "usage: rusty-monkey [--dialect <name>]";
 
 fn main() {
-    repl::start();
+    let mut keywords = KeywordTable::shared_default();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dialect" => {
+                let name = args.next().unwrap_or_default();
+                match KeywordTable::dialect(&name) {
+                    Some(table) => keywords = Arc::new(table),
+                    None => {
+                        eprintln!(
+                            "Unknown dialect {:?}, expected one of: {}",
+                            name,
+                            DIALECTS.join(", ")
+                        );
+                        process::exit(2);
+                    }
+                }
+            }
+            _ => {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            }
+        }
+    }
+
+    repl::start(keywords);
 }
@@ -0,0 +1,359 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, LazyLock},
+};
+
+use crate::{ast::Expression, lexer::is_operator_spelling, parser::Parser, token::TokenType};
+
+/**
+* Operator Precedence
+*
+* Spaced out, so custom operators can go in between.
+*/
+pub const LOWEST: i32 = 10;
+pub const EQUALS: i32 = 20; // ==
+pub const LESSGREATER: i32 = 30; // >, <, >= or <=
+pub const SUM: i32 = 40; // +
+pub const PRODUCT: i32 = 50; // *, / or %
+pub const PREFIX: i32 = 60; // -X or !X
+pub const CALL: i32 = 70; // my_function(X)
+
+/// The built-in binary operators, all left-associative.
+const BINARY: [(TokenType, i32); 11] = [
+    (TokenType::Eq, EQUALS),
+    (TokenType::NotEq, EQUALS),
+    (TokenType::Lt, LESSGREATER),
+    (TokenType::Gt, LESSGREATER),
+    (TokenType::LtEq, LESSGREATER),
+    (TokenType::GtEq, LESSGREATER),
+    (TokenType::Plus, SUM),
+    (TokenType::Minus, SUM),
+    (TokenType::Slash, PRODUCT),
+    (TokenType::Asterisk, PRODUCT),
+    (TokenType::Percent, PRODUCT),
+];
+
+static DEFAULT: LazyLock<Arc<OperatorTable>> = LazyLock::new(|| Arc::new(OperatorTable::default()));
+
+/// Parses an expression that starts at the parser's current token, leaving
+/// its last token as the current one. Gets the rule's precedence, which is
+/// what an operand should be parsed at.
+pub type PrefixParseFn = for<'src> fn(&mut Parser<'src>, i32) -> Option<Expression<'src>>;
+
+/// Parses the rest of an expression whose operator is the parser's current
+/// token, given the expression on its left. Gets the precedence to parse the
+/// right-hand side at, see `InfixRule::right_precedence`.
+pub type InfixParseFn =
+    for<'src> fn(&mut Parser<'src>, Expression<'src>, i32) -> Option<Expression<'src>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Associativity {
+    Left,  // a - b - c is (a - b) - c
+    Right, // a = b = c is a = (b = c)
+}
+
+/// How to parse a token at the start of an expression.
+#[derive(Debug, Clone, Copy)]
+pub struct PrefixRule {
+    pub precedence: i32,
+    pub parse: PrefixParseFn,
+}
+
+impl PrefixRule {
+    pub fn new(precedence: i32, parse: PrefixParseFn) -> PrefixRule {
+        PrefixRule { precedence, parse }
+    }
+
+    /// An operator in front of its operand, like `-x`, parsed into a
+    /// `PrefixExpression`. The operand binds at `precedence`, so with
+    /// `PREFIX`, `-a * b` is `(-a) * b`.
+    pub fn unary(precedence: i32) -> PrefixRule {
+        PrefixRule::new(precedence, |p, precedence| {
+            p.parse_prefix_expression(precedence)
+        })
+    }
+}
+
+/// How to parse a token that follows an expression.
+#[derive(Debug, Clone, Copy)]
+pub struct InfixRule {
+    pub precedence: i32, // has to be above LOWEST for the rule to ever apply
+    pub associativity: Associativity,
+    pub parse: InfixParseFn,
+}
+
+impl InfixRule {
+    pub fn new(precedence: i32, associativity: Associativity, parse: InfixParseFn) -> InfixRule {
+        InfixRule {
+            precedence,
+            associativity,
+            parse,
+        }
+    }
+
+    /// An operator between two operands, like `a + b`, parsed into an
+    /// `InfixExpression`.
+    pub fn binary(precedence: i32, associativity: Associativity) -> InfixRule {
+        InfixRule::new(precedence, associativity, |p, left, precedence| {
+            p.parse_infix_expression(left, precedence)
+        })
+    }
+
+    /// The right-hand side stops at any operator that doesn't bind tighter
+    /// than this. For a right-associative operator that includes itself, so
+    /// it's one less than the operator's own precedence.
+    pub fn right_precedence(&self) -> i32 {
+        match self.associativity {
+            Associativity::Left => self.precedence,
+            Associativity::Right => self.precedence - 1,
+        }
+    }
+}
+
+/// The Pratt parser's rules for each token type: how to parse it at the
+/// start of an expression, and how to parse it as an operator after one.
+///
+/// The default table holds Monkey's own expressions and operators. Embedders
+/// can change the precedence of the built-in ones, or add operators of their
+/// own with a rule for a `TokenType::Operator`, e.g.
+/// `TokenType::Operator("@".into())`. Spellings are `Arc<str>`s, so they can
+/// come from a config file or a plugin as well as from string literals.
+/// The lexer then reads `@` as an operator too, so the table is handed to
+/// `Lexer::with_operators`, and the parser uses the same one. Like
+/// `KeywordTable`, a lexer shares its table through an `Arc`.
+#[derive(Debug, Clone)]
+pub struct OperatorTable {
+    // Ordered, so the tokens in "expected an expression" errors are too
+    prefix: BTreeMap<TokenType, PrefixRule>,
+    infix: BTreeMap<TokenType, InfixRule>,
+    spellings: BTreeSet<Arc<str>>, // of the `TokenType::Operator`s with a rule
+}
+
+impl Default for OperatorTable {
+    fn default() -> OperatorTable {
+        let table = OperatorTable::empty()
+            .with_prefix(
+                TokenType::Ident,
+                PrefixRule::new(LOWEST, |p, _| Some(p.parse_identifier())),
+            )
+            .with_prefix(
+                TokenType::Int,
+                PrefixRule::new(LOWEST, |p, _| p.parse_integer_literal()),
+            )
+            .with_prefix(
+                TokenType::Float,
+                PrefixRule::new(LOWEST, |p, _| p.parse_float_literal()),
+            )
+            .with_prefix(
+                TokenType::String,
+                PrefixRule::new(LOWEST, |p, _| Some(p.parse_string_literal())),
+            )
+            .with_prefix(
+                TokenType::TemplateStart,
+                PrefixRule::new(LOWEST, |p, _| p.parse_template_literal()),
+            )
+            .with_prefix(
+                TokenType::True,
+                PrefixRule::new(LOWEST, |p, _| Some(p.parse_boolean())),
+            )
+            .with_prefix(
+                TokenType::False,
+                PrefixRule::new(LOWEST, |p, _| Some(p.parse_boolean())),
+            )
+            .with_prefix(
+                TokenType::LParen,
+                PrefixRule::new(LOWEST, |p, _| p.parse_grouped_expression()),
+            )
+            .with_prefix(
+                TokenType::If,
+                PrefixRule::new(LOWEST, |p, _| p.parse_if_expression()),
+            )
+            .with_prefix(
+                TokenType::Function,
+                PrefixRule::new(LOWEST, |p, _| p.parse_function_literal()),
+            )
+            .with_prefix(TokenType::Bang, PrefixRule::unary(PREFIX))
+            .with_prefix(TokenType::Minus, PrefixRule::unary(PREFIX))
+            .with_infix(
+                TokenType::LParen,
+                InfixRule::new(CALL, Associativity::Left, |p, function, _| {
+                    p.parse_call_expression(function)
+                }),
+            );
+
+        BINARY
+            .into_iter()
+            .fold(table, |table, (token_type, precedence)| {
+                table.with_infix(
+                    token_type,
+                    InfixRule::binary(precedence, Associativity::Left),
+                )
+            })
+    }
+}
+
+impl OperatorTable {
+    /// A table without any rules, so nothing can be parsed as an expression.
+    pub fn empty() -> OperatorTable {
+        OperatorTable {
+            prefix: BTreeMap::new(),
+            infix: BTreeMap::new(),
+            spellings: BTreeSet::new(),
+        }
+    }
+
+    /// The table parsers use unless they're given another one.
+    pub fn shared_default() -> Arc<OperatorTable> {
+        Arc::clone(&DEFAULT)
+    }
+
+    /// Sets how `token_type` is parsed at the start of an expression,
+    /// replacing any rule it had. See `spellings` for new operators.
+    ///
+    /// Panics if `token_type` is a `TokenType::Operator` whose spelling the
+    /// lexer can't read, see `spellings`.
+    pub fn with_prefix(mut self, token_type: TokenType, rule: PrefixRule) -> OperatorTable {
+        self.add_spelling(&token_type);
+        self.prefix.insert(token_type, rule);
+        self
+    }
+
+    /// Sets how `token_type` is parsed after an expression, replacing any
+    /// rule it had. See `spellings` for new operators.
+    ///
+    /// Panics if `token_type` is a `TokenType::Operator` whose spelling the
+    /// lexer can't read, see `spellings`.
+    pub fn with_infix(mut self, token_type: TokenType, rule: InfixRule) -> OperatorTable {
+        self.add_spelling(&token_type);
+        self.infix.insert(token_type, rule);
+        self
+    }
+
+    fn add_spelling(&mut self, token_type: &TokenType) {
+        if let TokenType::Operator(spelling) = token_type {
+            // An empty operator would be read everywhere without the lexer
+            // ever moving on, and others would change how comments, names or
+            // literals are lexed.
+            assert!(
+                is_operator_spelling(spelling),
+                "{:?} can't be used as an operator",
+                spelling
+            );
+            self.spellings.insert(Arc::clone(spelling));
+        }
+    }
+
+    pub fn prefix(&self, token_type: &TokenType) -> Option<PrefixRule> {
+        self.prefix.get(token_type).copied()
+    }
+
+    pub fn infix(&self, token_type: &TokenType) -> Option<InfixRule> {
+        self.infix.get(token_type).copied()
+    }
+
+    /// The tokens an expression can start with, i.e. the ones with a prefix
+    /// rule.
+    pub fn expression_start(&self) -> Vec<TokenType> {
+        self.prefix.keys().cloned().collect()
+    }
+
+    /// The operators added to the lexer's own, one for each
+    /// `TokenType::Operator` with a rule.
+    ///
+    /// The lexer reads the longest operator the input continues with, so `**`
+    /// can be added next to `*`. Where a spelling is also a built-in operator,
+    /// the built-in one wins. A spelling has to be made of symbols that don't
+    /// start anything else, so not whitespace, letters, digits, underscores,
+    /// quotes, backticks or the `//` and `/*` of a comment, and it can't end in
+    /// a `/` that would make one.
+    pub fn spellings(&self) -> impl Iterator<Item = &Arc<str>> {
+        self.spellings.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Associativity, InfixRule, OperatorTable, PrefixRule, CALL, PREFIX, PRODUCT, SUM};
+    use crate::token::TokenType;
+    use std::panic;
+
+    #[test]
+    fn test_default_operators() {
+        let table = OperatorTable::default();
+
+        let plus = table.infix(&TokenType::Plus).unwrap();
+        assert_eq!(plus.precedence, SUM);
+        assert_eq!(plus.associativity, Associativity::Left);
+        assert_eq!(plus.right_precedence(), SUM);
+        assert_eq!(
+            table.infix(&TokenType::Percent).unwrap().precedence,
+            PRODUCT
+        );
+        assert_eq!(table.infix(&TokenType::LParen).unwrap().precedence, CALL);
+        assert!(table.infix(&TokenType::Assign).is_none());
+        assert!(table.infix(&TokenType::Bang).is_none());
+
+        assert!(table.prefix(&TokenType::Minus).is_some());
+        assert!(table.prefix(&TokenType::Asterisk).is_none());
+        assert_eq!(table.expression_start().len(), 12);
+        assert!(OperatorTable::empty().expression_start().is_empty());
+    }
+
+    #[test]
+    fn test_operator_spellings() {
+        let rule = InfixRule::binary(PRODUCT, Associativity::Left);
+        let table = OperatorTable::default()
+            .with_infix(TokenType::Operator("@".into()), rule)
+            .with_prefix(TokenType::Operator("@".into()), PrefixRule::unary(PREFIX))
+            .with_infix(TokenType::Operator("**".into()), rule)
+            .with_infix(TokenType::Slash, rule);
+
+        let spellings: Vec<&str> = table.spellings().map(|s| &**s).collect();
+        assert_eq!(spellings, vec!["**", "@"]);
+        assert!(table.prefix(&TokenType::Operator("@".into())).is_some());
+        assert_eq!(OperatorTable::default().spellings().count(), 0);
+        assert_eq!(TokenType::Operator("**".into()).to_string(), "`**`");
+
+        // Spellings made at runtime don't have to be leaked
+        let spelling = String::from("<") + "|";
+        let table = table.with_infix(TokenType::Operator(spelling.into()), rule);
+        assert!(table.infix(&TokenType::Operator("<|".into())).is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "\"\" can't be used as an operator")]
+    fn test_empty_operator_spelling() {
+        let rule = InfixRule::binary(PRODUCT, Associativity::Left);
+        let _ = OperatorTable::default().with_infix(TokenType::Operator("".into()), rule);
+    }
+
+    #[test]
+    fn test_invalid_operator_spellings() {
+        let invalid = [
+            "/*", "//", "+//", "+/", "a+", "+_", "é", "1+", "+ +", "\"", "'", "`", "\n",
+        ];
+        for spelling in invalid {
+            let table = panic::catch_unwind(|| {
+                OperatorTable::default().with_prefix(
+                    TokenType::Operator(spelling.into()),
+                    PrefixRule::unary(PREFIX),
+                )
+            });
+            assert!(table.is_err(), "{:?} was accepted", spelling);
+        }
+
+        let rule = InfixRule::binary(PRODUCT, Associativity::Left);
+        let table = ["|>", "<=>", "/+", "@", "$", "~~", "#", "→"]
+            .into_iter()
+            .fold(OperatorTable::default(), |table, spelling| {
+                table.with_infix(TokenType::Operator(spelling.into()), rule)
+            });
+        assert_eq!(table.spellings().count(), 8);
+    }
+
+    #[test]
+    fn test_right_associativity() {
+        let rule = InfixRule::binary(SUM, Associativity::Right);
+        assert_eq!(rule.right_precedence(), SUM - 1);
+    }
+}
@@ -1,89 +1,180 @@
 use crate::ast::{
-    Expression, ExpressionStatement, IdentifierStruct, InfixExpressionStruct, IntegerLiteralStruct,
-    LetStatement, PrefixExpressionStruct, Program, ReturnStatement, Statement,
+    BlockStatement, BooleanStruct, CallExpressionStruct, ErrorStatement, ErrorStruct, Expression,
+    ExpressionStatement, FloatLiteralStruct, FunctionLiteralStruct, IdentifierStruct,
+    IfExpressionStruct, InfixExpressionStruct, IntegerLiteralStruct, InterpolationStruct,
+    LetStatement, PrefixExpressionStruct, Program, ReturnStatement, Statement, StringLiteralStruct,
+    TemplatePart,
 };
-use crate::token::TokenType;
+use crate::diagnostic::{
+    Diagnostic, EXPECTED_EXPRESSION, FLOAT_OUT_OF_RANGE, INTEGER_OUT_OF_RANGE, NESTING_TOO_DEEP,
+    UNCLOSED_BLOCK, UNEXPECTED_TOKEN,
+};
+use crate::operators::{OperatorTable, LOWEST};
+use crate::symbol::Symbol;
+use crate::token::{Position, TokenType};
 use crate::{lexer::Lexer, token::Token};
-
-/**
-* Operator Precedence
-*/
-const LOWEST: i32 = 1;
-const EQUALS: i32 = 2; // ==
-const LESSGREATER: i32 = 3; // > or <
-const SUM: i32 = 4; // +
-const PRODUCT: i32 = 5; // *
-const PREFIX: i32 = 6; // -X or !X
-const CALL: i32 = 7; // my_function(X)
-
-#[derive(Clone)]
-struct ParserError(String);
-impl ParserError {
-    fn new(error: String) -> ParserError {
-        ParserError(error)
-    }
-}
-
-struct Parser {
-    l: Lexer,
-    current_token: Token,
-    peek_token: Token,
-    errors: Vec<ParserError>,
+use std::{borrow::Cow, mem, sync::Arc};
+
+/// How deeply expressions and blocks can be nested inside each other. Each
+/// level takes a few stack frames, so without a limit something like 100,000
+/// `(`s in a row would overflow the stack.
+const MAX_NESTING: usize = 128;
+
+/// A Pratt parser that builds a `Program` out of the tokens of a `Lexer`.
+///
+/// Parsing doesn't stop at the first error. The parser reports it, skips to
+/// the next statement and carries on, so `parse_program` always returns a
+/// `Program`, with `Error` nodes where something had to be skipped. `errors`
+/// lists everything that went wrong, including the lexer's errors.
+///
+/// Which tokens start an expression, and which operators can follow one, is
+/// looked up in an `OperatorTable`. The parser uses its lexer's table, so the
+/// default one is swapped with `Lexer::with_operators`.
+pub struct Parser<'src> {
+    l: Lexer<'src>,
+    current_token: Token<'src>,
+    peek_token: Token<'src>,
+    current_docs: Vec<Cow<'src, str>>, // doc comments that came right before current_token
+    peek_docs: Vec<Cow<'src, str>>,    // doc comments that came right before peek_token
+    errors: Vec<Diagnostic>,
+    operators: Arc<OperatorTable>,
+    depth: usize, // how many expressions and blocks we're in the middle of parsing
+    illegal_tokens: usize, // how many `Illegal` tokens have been the current token
 }
 
-impl Parser {
-    fn new(mut l: Lexer) -> Parser {
+impl<'src> Parser<'src> {
+    pub fn new(mut l: Lexer<'src>) -> Parser<'src> {
         // Get the first two tokens for Parser
-        let current_token = l.next_token();
-        let peek_token = l.next_token();
+        let mut current_docs = Vec::new();
+        let current_token = next_non_doc_token(&mut l, None, &mut current_docs);
+        let mut peek_docs = Vec::new();
+        let peek_token = next_non_doc_token(&mut l, Some(&current_token), &mut peek_docs);
 
         Parser {
+            operators: Arc::clone(l.operators()),
             l,
             current_token,
             peek_token,
+            current_docs,
+            peek_docs,
             errors: Vec::new(),
+            depth: 0,
+            illegal_tokens: 0,
         }
     }
 
+    pub fn operators(&self) -> &OperatorTable {
+        &self.operators
+    }
+
     /**
      * Error handling
      */
-    fn errors(&self) -> Vec<ParserError> {
-        self.errors.clone()
+    /// Lexer errors come first, followed by the errors found while parsing.
+    pub fn errors(&self) -> Vec<Diagnostic> {
+        let mut errors: Vec<Diagnostic> = self.l.errors().iter().map(Diagnostic::from).collect();
+        errors.extend(self.errors.iter().cloned());
+        errors
     }
 
     fn peek_error(&mut self, t: TokenType) {
-        let error_message = format!(
-            "Expected next token to be {:?}, got {:?} instead.",
-            { t },
-            self.peek_token.token_type
-        );
-        self.errors.push(ParserError::new(error_message));
+        let message = format!("Expected {}, found {}", t, describe(&self.peek_token));
+        let mut diagnostic = Diagnostic::error(UNEXPECTED_TOKEN, message, self.peek_token.span)
+            .with_expected(vec![t])
+            .with_found(self.peek_token.token_type.clone());
+        if is_inserted_semicolon(&self.peek_token) {
+            diagnostic = diagnostic.with_note(SEMICOLON_NOTE);
+        }
+        self.errors.push(diagnostic);
     }
     /**
      * Advance token
      */
-    fn next_token(&mut self) {
-        self.current_token = self.peek_token.clone();
-        self.peek_token = self.l.next_token();
+    pub fn next_token(&mut self) {
+        self.current_docs = mem::take(&mut self.peek_docs);
+        let peek_token =
+            next_non_doc_token(&mut self.l, Some(&self.peek_token), &mut self.peek_docs);
+        self.current_token = mem::replace(&mut self.peek_token, peek_token);
+        if self.cur_token_is(TokenType::Illegal) {
+            self.illegal_tokens += 1;
+        }
     }
     /**
      * Parse program
      */
-    fn parse_program(&mut self) -> Program {
+    pub fn parse_program(&mut self) -> Program<'src> {
         let mut program = Program::new();
 
         while !self.cur_token_is(TokenType::Eof) {
-            let statement = self.parse_statement();
-            if let Some(stmt) = statement {
-                program.statements.push(stmt);
+            if !self.parse_statement_into(&mut program.statements, false) {
+                self.next_token();
             }
-            self.next_token();
         }
 
+        program.symbols = self.l.take_interner();
         program
     }
 
+    /**
+     * Error recovery
+     */
+    /// Parses the statement at the current token onto `statements`, leaving
+    /// the last token of the statement as the current one.
+    ///
+    /// If the parser reports an error along the way, or the statement runs into
+    /// an `Illegal` token the lexer has already reported, the rest of the
+    /// statement is skipped with `synchronize` and we return true. The current
+    /// token is then already the start of whatever comes next. A statement too
+    /// broken to be parsed at all becomes a `Statement::Error`.
+    fn parse_statement_into(
+        &mut self,
+        statements: &mut Vec<Statement<'src>>,
+        in_block: bool,
+    ) -> bool {
+        let start = self.current_token.clone();
+        let errors = self.errors.len();
+        let illegal_tokens = self.illegal_tokens;
+        let statement = self.parse_statement();
+
+        let broken = self.errors.len() > errors
+            || self.illegal_tokens > illegal_tokens
+            || start.token_type == TokenType::Illegal
+            || self.peek_token_is(TokenType::Illegal);
+        if !broken {
+            statements.extend(statement);
+            return false;
+        }
+
+        self.synchronize(start.span.start, in_block);
+        statements.push(statement.unwrap_or_else(|| Statement::Error(ErrorStatement::new(start))));
+        true
+    }
+
+    /// Skips tokens up to where the next statement is likely to start: just
+    /// past a `;`, or at a keyword that starts a statement, or at the `}` that
+    /// closes the block we're in. Errors in the skipped tokens would mostly be
+    /// knock-on effects of the first one, so they aren't reported.
+    ///
+    /// `start` is where the broken statement started, which we always move
+    /// past, so that the parser keeps making progress.
+    fn synchronize(&mut self, start: Position, in_block: bool) {
+        if self.current_token.span.start == start {
+            self.next_token();
+        }
+
+        loop {
+            match self.current_token.token_type {
+                TokenType::Semicolon => {
+                    self.next_token();
+                    return;
+                }
+                TokenType::Eof | TokenType::Let | TokenType::Return | TokenType::If => return,
+                TokenType::RBrace if in_block => return,
+                _ => self.next_token(),
+            }
+        }
+    }
+
     /**
      * Helper methods for checking tokens
      */
@@ -95,7 +186,16 @@ impl Parser {
         self.peek_token.token_type == t
     }
 
-    fn expect_peek(&mut self, t: TokenType) -> bool {
+    pub fn current_token(&self) -> &Token<'src> {
+        &self.current_token
+    }
+
+    pub fn peek_token(&self) -> &Token<'src> {
+        &self.peek_token
+    }
+
+    /// Moves on to the next token if it's a `t`, and reports an error if not.
+    pub fn expect_peek(&mut self, t: TokenType) -> bool {
         if self.peek_token_is(t.clone()) {
             self.next_token();
             return true;
@@ -109,232 +209,603 @@ impl Parser {
     /**
      * Methods for parsing
      */
-    fn parse_statement(&mut self) -> Option<Statement> {
+    fn parse_statement(&mut self) -> Option<Statement<'src>> {
         match self.current_token.token_type {
             TokenType::Let => self.parse_let_statement(),
             TokenType::Return => self.parse_return_statement(),
+            // An empty statement, e.g. a `;` after an inserted one
+            TokenType::Semicolon => None,
             _ => self.parse_expression_statement(),
         }
     }
 
-    fn parse_let_statement(&mut self) -> Option<Statement> {
+    fn parse_let_statement(&mut self) -> Option<Statement<'src>> {
         let let_token = self.current_token.clone();
+        let docs = mem::take(&mut self.current_docs);
 
         if !self.expect_peek(TokenType::Ident) {
             return None;
         }
 
-        let statement_name = IdentifierStruct::new(
-            self.current_token.clone(),
-            self.current_token.literal.clone(),
-        );
+        let statement_name =
+            IdentifierStruct::new(self.current_token.clone(), self.current_symbol());
 
         if !self.expect_peek(TokenType::Assign) {
             return None;
         }
 
-        // TODO: Skipping the expressions until we encounter
-        // a semicolon
-        while !self.cur_token_is(TokenType::Semicolon) {
-            self.next_token();
+        self.next_token();
+
+        let value = self.parse_expression_or_error(LOWEST);
+        if !matches!(value, Expression::Error(_)) {
+            self.expect_statement_end();
         }
 
-        let statement = Statement::Let(LetStatement::new(let_token, statement_name, None));
+        let statement = Statement::Let(LetStatement::new(let_token, statement_name, value, docs));
 
         Some(statement)
     }
 
-    fn parse_return_statement(&mut self) -> Option<Statement> {
+    fn parse_return_statement(&mut self) -> Option<Statement<'src>> {
         let return_token = self.current_token.clone();
 
         self.next_token();
 
-        // TODO: Skipping the expressions until we encounter
-        // a semicolon
-        while !self.cur_token_is(TokenType::Semicolon) {
-            self.next_token();
+        let value = self.parse_expression_or_error(LOWEST);
+        if !matches!(value, Expression::Error(_)) {
+            self.expect_statement_end();
         }
 
-        let statement = Statement::Return(ReturnStatement::new(return_token, None));
+        let statement = Statement::Return(ReturnStatement::new(return_token, value));
 
         Some(statement)
     }
 
-    fn parse_expression_statement(&mut self) -> Option<Statement> {
+    fn parse_expression_statement(&mut self) -> Option<Statement<'src>> {
         let expression_token = self.current_token.clone();
-        let expression = self.parse_expression(LOWEST);
-
-        if self.peek_token_is(TokenType::Semicolon) {
-            self.next_token()
+        let expression = self.parse_expression_or_error(LOWEST);
+        if !matches!(expression, Expression::Error(_)) {
+            self.expect_statement_end();
         }
 
-        let statement = Statement::Expression(ExpressionStatement::new(
-            expression_token,
-            Some(expression)?,
-        ));
+        let statement =
+            Statement::Expression(ExpressionStatement::new(expression_token, expression));
 
         Some(statement)
     }
 
+    /// Moves on to the `;` that ends the statement, which the lexer inserts at
+    /// the end of a line. Without one, the statement has to be the last in its
+    /// block or in the program, or it runs into the next one, as in
+    /// `let x = 1 let y = 2`. A statement that ended in an error is skipped
+    /// with `synchronize` instead, so callers don't check it.
+    fn expect_statement_end(&mut self) {
+        match self.peek_token.token_type {
+            TokenType::Semicolon => self.next_token(),
+            // The lexer has already reported an `Illegal` token
+            TokenType::RBrace | TokenType::Eof | TokenType::Illegal => {}
+            _ => {
+                let message = format!(
+                    "Expected {} or a line break, found {}",
+                    TokenType::Semicolon,
+                    describe(&self.peek_token)
+                );
+                self.errors.push(
+                    Diagnostic::error(UNEXPECTED_TOKEN, message, self.peek_token.span)
+                        .with_expected(vec![TokenType::Semicolon])
+                        .with_found(self.peek_token.token_type.clone()),
+                );
+            }
+        }
+    }
+
     /**
      * Parse expressions
      */
+    /// Parses an expression starting at the current token, taking in every
+    /// operator that binds tighter than `precedence`. Leaves the last token of
+    /// the expression as the current one.
     // TODO: Options everywhere! Probably should remove eventually
-    fn parse_expression(&mut self, precedence: i32) -> Option<Expression> {
-        let mut left_exp = self.prefix_parse_fns(self.current_token.token_type.clone());
-        if let None = left_exp {
-            self.no_prefix_parse_fn_error(self.current_token.token_type.clone());
+    pub fn parse_expression(&mut self, precedence: i32) -> Option<Expression<'src>> {
+        if !self.enter() {
             return None;
         }
+        let expression = self.parse_nested_expression(precedence);
+        self.depth -= 1;
 
-        while !self.peek_token_is(TokenType::Semicolon) && precedence < self.peek_precedence() {
-            let infix_fn_exists = Parser::check_infix_parse_fns(self.peek_token.token_type.clone());
-            if let None = infix_fn_exists {
-                return left_exp;
+        expression
+    }
+
+    /// The body of `parse_expression`, once we know we're not nested too
+    /// deeply to carry on.
+    fn parse_nested_expression(&mut self, precedence: i32) -> Option<Expression<'src>> {
+        let token_type = self.current_token.token_type.clone();
+        let mut left_exp = match self.operators.prefix(&token_type) {
+            Some(rule) => (rule.parse)(self, rule.precedence)?,
+            // The lexer has already reported whatever was wrong with this token
+            None if token_type == TokenType::Illegal => return None,
+            None => {
+                self.no_prefix_parse_fn_error(token_type);
+                return None;
             }
+        };
+
+        while !self.peek_token_is(TokenType::Semicolon) {
+            let rule = match self.operators.infix(&self.peek_token.token_type) {
+                Some(rule) if precedence < rule.precedence => rule,
+                _ => break,
+            };
 
             self.next_token();
 
-            left_exp =
-                self.infix_parse_fns(self.current_token.token_type.clone(), left_exp.unwrap());
+            left_exp = (rule.parse)(self, left_exp, rule.right_precedence())?;
         }
 
-        left_exp
+        Some(left_exp)
     }
 
-    // TODO: tmp Option return type until we implement all TokenTypes
-    fn prefix_parse_fns(&mut self, token_type: TokenType) -> Option<Expression> {
-        match token_type {
-            TokenType::Ident => Some(self.parse_identifier()),
-            TokenType::Int => Some(self.parse_integer_literal()),
-            TokenType::Bang => Some(self.parse_prefix_expression()),
-            TokenType::Minus => Some(self.parse_prefix_expression()),
-            _ => None,
-        }
+    /// Like `parse_expression`, but with an `Expression::Error` in place of an
+    /// expression that couldn't be parsed.
+    fn parse_expression_or_error(&mut self, precedence: i32) -> Expression<'src> {
+        let token = self.current_token.clone();
+        self.parse_expression(precedence)
+            .unwrap_or_else(|| Expression::Error(ErrorStruct::new(token)))
     }
 
-    fn parse_identifier(&mut self) -> Expression {
+    pub(crate) fn parse_identifier(&mut self) -> Expression<'src> {
         Expression::Identifier(IdentifierStruct::new(
             self.current_token.clone(),
-            self.current_token.literal.clone(),
+            self.current_symbol(),
         ))
     }
 
-    fn parse_integer_literal(&mut self) -> Expression {
-        let value = match self.current_token.literal.parse::<i64>() {
-            Ok(val) => Some(val),
+    /// The lexer interns every identifier it reads, but an `Ident` token made
+    /// some other way may not have a symbol yet.
+    fn current_symbol(&mut self) -> Symbol {
+        match self.current_token.symbol {
+            Some(symbol) => symbol,
+            None => self.l.intern(&self.current_token.literal),
+        }
+    }
+
+    /// Int tokens can have a `0x`, `0o` or `0b` prefix and `_` separators,
+    /// which the lexer has already checked are well formed. All that can go
+    /// wrong here is the value not fitting in an i64.
+    pub(crate) fn parse_integer_literal(&mut self) -> Option<Expression<'src>> {
+        let literal = self.current_token.literal.replace('_', "");
+        let (digits, radix) = match literal.get(..2) {
+            Some("0x" | "0X") => (&literal[2..], 16),
+            Some("0o" | "0O") => (&literal[2..], 8),
+            Some("0b" | "0B") => (&literal[2..], 2),
+            _ => (&literal[..], 10),
+        };
+
+        match i64::from_str_radix(digits, radix) {
+            Ok(value) => Some(Expression::IntegerLiteral(IntegerLiteralStruct::new(
+                self.current_token.clone(),
+                value,
+            ))),
             Err(_) => {
-                let msg = format!("Could not parse {} as integer", self.current_token.literal);
-                self.errors.push(ParserError::new(msg));
+                let message = format!(
+                    "Integer literal {} does not fit in a 64-bit integer",
+                    self.current_token.literal
+                );
+                self.errors.push(
+                    Diagnostic::error(INTEGER_OUT_OF_RANGE, message, self.current_token.span)
+                        .with_found(TokenType::Int)
+                        .with_note(format!("the largest integer is {}", i64::MAX))
+                        .with_help("use a float literal, e.g. `1e20`, for larger numbers"),
+                );
                 None
             }
-        };
+        }
+    }
+
+    pub(crate) fn parse_float_literal(&mut self) -> Option<Expression<'src>> {
+        let literal = self.current_token.literal.replace('_', "");
+
+        match literal.parse::<f64>() {
+            Ok(value) if value.is_finite() => Some(Expression::FloatLiteral(
+                FloatLiteralStruct::new(self.current_token.clone(), value),
+            )),
+            _ => {
+                let message = format!(
+                    "Float literal {} is out of range",
+                    self.current_token.literal
+                );
+                self.errors.push(
+                    Diagnostic::error(FLOAT_OUT_OF_RANGE, message, self.current_token.span)
+                        .with_found(TokenType::Float)
+                        .with_note(format!("the largest float is about {:e}", f64::MAX)),
+                );
+                None
+            }
+        }
+    }
+
+    pub(crate) fn parse_string_literal(&mut self) -> Expression<'src> {
+        Expression::StringLiteral(StringLiteralStruct::new(
+            self.current_token.clone(),
+            self.current_token.literal.clone(),
+        ))
+    }
+
+    pub(crate) fn parse_boolean(&mut self) -> Expression<'src> {
+        Expression::Boolean(BooleanStruct::new(
+            self.current_token.clone(),
+            self.cur_token_is(TokenType::True),
+        ))
+    }
+
+    /// Parentheses don't get a node of their own. They only reset the
+    /// precedence, so whatever is inside them binds as a single operand.
+    pub(crate) fn parse_grouped_expression(&mut self) -> Option<Expression<'src>> {
+        self.next_token();
+
+        let expression = self.parse_expression(LOWEST)?;
+
+        if !self.expect_peek(TokenType::RParen) {
+            return None;
+        }
+
+        Some(expression)
+    }
+
+    /// The condition doesn't need parentheses around it, since the `{` of the
+    /// consequence marks where it ends. Like in Go, `else` has to be on the same
+    /// line as the `}` before it, or a semicolon is inserted between them.
+    pub(crate) fn parse_if_expression(&mut self) -> Option<Expression<'src>> {
+        let token = self.current_token.clone();
+
+        self.next_token();
+        let condition = self.parse_expression(LOWEST)?;
+
+        if !self.expect_peek(TokenType::LBrace) {
+            return None;
+        }
+        let consequence = self.parse_block_statement()?;
+
+        let mut alternative = None;
+        if self.peek_token_is(TokenType::Else) {
+            self.next_token();
+
+            if self.peek_token_is(TokenType::If) {
+                self.next_token();
+                let if_token = self.current_token.clone();
+                // Each `else if` is nested in the one before it, so a long
+                // chain counts against the nesting limit too.
+                if !self.enter() {
+                    return None;
+                }
+                let else_if = self.parse_if_expression();
+                self.depth -= 1;
+                let else_if = else_if?;
+                let statement = ExpressionStatement::new(if_token.clone(), else_if);
+                alternative = Some(BlockStatement::new(
+                    if_token,
+                    vec![Statement::Expression(statement)],
+                ));
+            } else {
+                if !self.expect_peek(TokenType::LBrace) {
+                    return None;
+                }
+                alternative = Some(self.parse_block_statement()?);
+            }
+        }
+
+        Some(Expression::IfExpression(IfExpressionStruct::new(
+            token,
+            condition,
+            consequence,
+            alternative,
+        )))
+    }
+
+    /// Parses statements up to the `}` matching the current `{`, leaving the
+    /// `}` as the current token.
+    fn parse_block_statement(&mut self) -> Option<BlockStatement<'src>> {
+        if !self.enter() {
+            return None;
+        }
+        let block = self.parse_block_contents();
+        self.depth -= 1;
+
+        block
+    }
+
+    fn parse_block_contents(&mut self) -> Option<BlockStatement<'src>> {
+        let token = self.current_token.clone();
+        let mut statements = Vec::new();
+
+        self.next_token();
+
+        while !self.cur_token_is(TokenType::RBrace) {
+            if self.cur_token_is(TokenType::Eof) {
+                let message = format!(
+                    "Expected {} to close the block, found {}",
+                    TokenType::RBrace,
+                    TokenType::Eof
+                );
+                self.errors.push(
+                    Diagnostic::error(UNCLOSED_BLOCK, message, self.current_token.span)
+                        .with_expected(vec![TokenType::RBrace])
+                        .with_found(TokenType::Eof)
+                        .with_note(format!("the block starts at {}", token.span.start)),
+                );
+                return None;
+            }
+            if !self.parse_statement_into(&mut statements, true) {
+                self.next_token();
+            }
+        }
+
+        Some(BlockStatement::new(token, statements))
+    }
+
+    pub(crate) fn parse_function_literal(&mut self) -> Option<Expression<'src>> {
+        let token = self.current_token.clone();
+
+        if !self.expect_peek(TokenType::LParen) {
+            return None;
+        }
+        let parameters = self.parse_function_parameters()?;
+
+        if !self.expect_peek(TokenType::LBrace) {
+            return None;
+        }
+        let body = self.parse_block_statement()?;
+
+        Some(Expression::FunctionLiteral(FunctionLiteralStruct::new(
+            token, parameters, body,
+        )))
+    }
 
-        Expression::IntegerLiteral(IntegerLiteralStruct::new(self.current_token.clone(), value))
+    /// Parses the identifiers in `(x, y, z)`, starting on the `(` and ending on
+    /// the `)`. A trailing comma is allowed, so the `)` can go on a line of its
+    /// own without a semicolon being inserted before it.
+    fn parse_function_parameters(&mut self) -> Option<Vec<IdentifierStruct<'src>>> {
+        let mut parameters = Vec::new();
+
+        if self.peek_token_is(TokenType::RParen) {
+            self.next_token();
+            return Some(parameters);
+        }
+
+        loop {
+            if !self.expect_peek(TokenType::Ident) {
+                return None;
+            }
+            parameters.push(IdentifierStruct::new(
+                self.current_token.clone(),
+                self.current_symbol(),
+            ));
+
+            if !self.peek_token_is(TokenType::Comma) {
+                break;
+            }
+            self.next_token();
+            if self.peek_token_is(TokenType::RParen) {
+                break;
+            }
+        }
+
+        if !self.expect_peek(TokenType::RParen) {
+            return None;
+        }
+
+        Some(parameters)
+    }
+
+    /// The lexer splits a template string into its text and the tokens of
+    /// each interpolated expression, between `InterpolationStart` and
+    /// `InterpolationEnd`.
+    pub(crate) fn parse_template_literal(&mut self) -> Option<Expression<'src>> {
+        let token = self.current_token.clone();
+        let mut parts = Vec::new();
+
+        loop {
+            self.next_token();
+            match self.current_token.token_type {
+                TokenType::TemplateText => {
+                    parts.push(TemplatePart::Text(self.current_token.literal.clone()))
+                }
+                TokenType::InterpolationStart => {
+                    self.next_token();
+                    let expression = self.parse_expression(LOWEST)?;
+                    if !self.expect_peek(TokenType::InterpolationEnd) {
+                        return None;
+                    }
+                    parts.push(TemplatePart::Expression(expression));
+                }
+                TokenType::TemplateEnd => break,
+                // The lexer has already reported the unterminated template
+                _ => return None,
+            }
+        }
+
+        Some(Expression::Interpolation(InterpolationStruct::new(
+            token, parts,
+        )))
+    }
+
+    /// Goes a level deeper into nested expressions and blocks, or reports an
+    /// error if that's deeper than `MAX_NESTING`. Every successful call has to
+    /// be matched by taking one off `depth` again.
+    fn enter(&mut self) -> bool {
+        if self.depth == MAX_NESTING {
+            self.nesting_error();
+            return false;
+        }
+        self.depth += 1;
+        true
+    }
+
+    fn nesting_error(&mut self) {
+        let message = format!("Code is nested more than {} levels deep", MAX_NESTING);
+        self.errors.push(
+            Diagnostic::error(NESTING_TOO_DEEP, message, self.current_token.span)
+                .with_found(self.current_token.token_type.clone())
+                .with_help("split the expression up with `let` statements"),
+        );
     }
 
     fn no_prefix_parse_fn_error(&mut self, t: TokenType) {
-        let msg = format!("No prefix parse function found for {:?}", t);
-        self.errors.push(ParserError(msg));
+        let message = format!(
+            "Expected an expression, found {}",
+            describe(&self.current_token)
+        );
+        let mut diagnostic =
+            Diagnostic::error(EXPECTED_EXPRESSION, message, self.current_token.span)
+                .with_expected(self.operators.expression_start())
+                .with_found(t.clone());
+        if is_inserted_semicolon(&self.current_token) {
+            diagnostic = diagnostic.with_note(SEMICOLON_NOTE);
+        }
+        if t == TokenType::Else {
+            diagnostic = diagnostic.with_help("put `else` on the same line as the `}` before it");
+        }
+        self.errors.push(diagnostic);
     }
 
-    fn parse_prefix_expression(&mut self) -> Expression {
+    pub(crate) fn parse_prefix_expression(&mut self, precedence: i32) -> Option<Expression<'src>> {
         let token = self.current_token.clone();
         let operator = self.current_token.literal.clone();
 
         self.next_token();
 
-        let right = self.parse_expression(PREFIX).unwrap();
+        let right = self.parse_expression(precedence)?;
 
-        Expression::PrefixExpression(PrefixExpressionStruct::new(token, operator, right))
+        Some(Expression::PrefixExpression(PrefixExpressionStruct::new(
+            token, operator, right,
+        )))
     }
 
-    // TODO: tmp Option return type until we implement all TokenTypes
-    fn infix_parse_fns(
+    pub(crate) fn parse_infix_expression(
         &mut self,
-        token_type: TokenType,
-        left_expression: Expression,
-    ) -> Option<Expression> {
-        match token_type {
-            TokenType::Plus => Some(self.parse_infix_expression(left_expression)),
-            TokenType::Minus => Some(self.parse_infix_expression(left_expression)),
-            TokenType::Slash => Some(self.parse_infix_expression(left_expression)),
-            TokenType::Asterisk => Some(self.parse_infix_expression(left_expression)),
-            TokenType::Eq => Some(self.parse_infix_expression(left_expression)),
-            TokenType::NotEq => Some(self.parse_infix_expression(left_expression)),
-            TokenType::Lt => Some(self.parse_infix_expression(left_expression)),
-            TokenType::Gt => Some(self.parse_infix_expression(left_expression)),
-            _ => None,
-        }
-    }
-    // Should match the infix_parse_fns' match arms above.
-    // Needed because parse_expression needs to check if the corresponding infix_parse_fn
-    // exists first, and if it does, call next_token() before calling infix_parse_fns on the
-    // token that it checked.
-    fn check_infix_parse_fns(token_type: TokenType) -> Option<()> {
-        match token_type {
-            TokenType::Plus => Some(()),
-            TokenType::Minus => Some(()),
-            TokenType::Slash => Some(()),
-            TokenType::Asterisk => Some(()),
-            TokenType::Eq => Some(()),
-            TokenType::NotEq => Some(()),
-            TokenType::Lt => Some(()),
-            TokenType::Gt => Some(()),
-            _ => None,
-        }
-    }
-
-    fn parse_infix_expression(&mut self, left: Expression) -> Expression {
+        left: Expression<'src>,
+        precedence: i32,
+    ) -> Option<Expression<'src>> {
         let token = self.current_token.clone();
         let operator = self.current_token.literal.clone();
 
-        let precedence = self.cur_precedence();
         self.next_token();
-        let right = self.parse_expression(precedence).unwrap();
+        let right = self.parse_expression(precedence)?;
 
-        Expression::InfixExpression(InfixExpressionStruct::new(token, left, operator, right))
+        Some(Expression::InfixExpression(InfixExpressionStruct::new(
+            token, left, operator, right,
+        )))
     }
 
-    fn precedences(token_type: TokenType) -> i32 {
-        match token_type {
-            TokenType::Eq => EQUALS,
-            TokenType::NotEq => EQUALS,
-            TokenType::Lt => LESSGREATER,
-            TokenType::Gt => LESSGREATER,
-            TokenType::Plus => SUM,
-            TokenType::Minus => SUM,
-            TokenType::Slash => PRODUCT,
-            TokenType::Asterisk => PRODUCT,
-            _ => LOWEST,
+    pub(crate) fn parse_call_expression(
+        &mut self,
+        function: Expression<'src>,
+    ) -> Option<Expression<'src>> {
+        let token = self.current_token.clone();
+        let arguments = self.parse_call_arguments()?;
+
+        Some(Expression::CallExpression(CallExpressionStruct::new(
+            token, function, arguments,
+        )))
+    }
+
+    /// Parses the expressions in `(a, b + c)`, starting on the `(` and ending
+    /// on the `)`. Like parameters, they can have a trailing comma.
+    fn parse_call_arguments(&mut self) -> Option<Vec<Expression<'src>>> {
+        let mut arguments = Vec::new();
+
+        if self.peek_token_is(TokenType::RParen) {
+            self.next_token();
+            return Some(arguments);
+        }
+
+        self.next_token();
+        arguments.push(self.parse_expression(LOWEST)?);
+
+        while self.peek_token_is(TokenType::Comma) {
+            self.next_token();
+            if self.peek_token_is(TokenType::RParen) {
+                break;
+            }
+            self.next_token();
+            arguments.push(self.parse_expression(LOWEST)?);
+        }
+
+        if !self.expect_peek(TokenType::RParen) {
+            return None;
         }
+
+        Some(arguments)
     }
+}
+
+const SEMICOLON_NOTE: &str =
+    "a semicolon is inserted at the end of a line that could end a statement";
 
-    fn peek_precedence(&self) -> i32 {
-        Parser::precedences(self.peek_token.token_type.clone())
+/// Whether `tok` is a semicolon the lexer inserted at the end of a line.
+fn is_inserted_semicolon(tok: &Token) -> bool {
+    tok.token_type == TokenType::Semicolon && tok.literal == "\n"
+}
+
+/// How `tok` is described in error messages.
+fn describe(tok: &Token) -> String {
+    if is_inserted_semicolon(tok) {
+        "the end of the line".to_string()
+    } else {
+        tok.token_type.to_string()
     }
+}
 
-    fn cur_precedence(&self) -> i32 {
-        Parser::precedences(self.current_token.token_type.clone())
+/// Doc comments aren't part of the grammar, so the parser never sees them as
+/// tokens. Instead they're collected into `docs` and handed to whatever comes
+/// next, which only `let` statements currently make use of.
+///
+/// Only doc comments that start their own line count. One at the end of a line
+/// of code, after the `previous` token, is about that code rather than what
+/// follows, so it's dropped.
+fn next_non_doc_token<'src>(
+    l: &mut Lexer<'src>,
+    previous: Option<&Token>,
+    docs: &mut Vec<Cow<'src, str>>,
+) -> Token<'src> {
+    let mut line = previous.map(|tok| tok.span.end.line);
+    loop {
+        let tok = l.next_token();
+        if tok.token_type != TokenType::DocComment {
+            return tok;
+        }
+        if line.is_none_or(|line| tok.span.start.line > line) {
+            docs.push(tok.literal);
+        }
+        line = Some(tok.span.end.line);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::ast::{Expression, Node, Program, Statement};
+    use crate::diagnostic::{
+        Severity, EXPECTED_EXPRESSION, INTEGER_OUT_OF_RANGE, NESTING_TOO_DEEP,
+        UNEXPECTED_CHARACTER, UNEXPECTED_TOKEN,
+    };
     use crate::lexer::Lexer;
-    use crate::parser::Parser;
+    use crate::operators::{
+        Associativity, InfixRule, OperatorTable, PrefixRule, EQUALS, PREFIX, PRODUCT, SUM,
+    };
+    use crate::parser::{Parser, MAX_NESTING};
+    use crate::symbol::Interner;
+    use crate::token::{Position, Span, TokenType};
+    use std::sync::Arc;
 
     struct ExpectedIdentifier(String);
 
     fn check_parser_errors(p: Parser) {
         let errors = p.errors();
-        if errors.len() == 0 {
+        if errors.is_empty() {
             return;
         }
         println!("Parser has {} errors.", errors.len());
         errors.iter().for_each(|err| {
-            println!("Parser error: {}", err.0);
+            println!("Parser error: {}", err);
         });
         panic!()
     }
@@ -350,8 +821,9 @@ mod tests {
 let x = 5;
 let y = 10;
 let foobar = 838383;
+let total = x + y * foobar;
 "#;
-        let l = Lexer::new(input.to_string());
+        let l = Lexer::new(input);
         let mut p = Parser::new(l);
 
         let program = p.parse_program();
@@ -359,40 +831,66 @@ let foobar = 838383;
         check_parser_errors(p);
 
         assert!(
-            program.statements.len() == 3,
-            "Program.statements does not contain 3 statements, got: {}. Statements: {:?}",
+            program.statements.len() == 4,
+            "Program.statements does not contain 4 statements, got: {}. Statements: {:?}",
             program.statements.len(),
             program.statements
         );
 
-        let tests: Vec<ExpectedIdentifier> = vec![
-            ExpectedIdentifier("x".to_string()),
-            ExpectedIdentifier("y".to_string()),
-            ExpectedIdentifier("foobar".to_string()),
+        let tests: Vec<(ExpectedIdentifier, &str)> = vec![
+            (ExpectedIdentifier("x".to_string()), "5"),
+            (ExpectedIdentifier("y".to_string()), "10"),
+            (ExpectedIdentifier("foobar".to_string()), "838383"),
+            (
+                ExpectedIdentifier("total".to_string()),
+                "(x + (y * foobar))",
+            ),
         ];
 
-        for (i, expected_identifier) in tests.iter().enumerate() {
+        for (i, (expected_identifier, expected_value)) in tests.iter().enumerate() {
             let statement = program
                 .statements
                 .get(i)
                 .expect("Failed to index into program.statements");
 
-            assert!(test_let_statement(statement, expected_identifier.0.clone()));
+            assert!(test_let_statement(
+                statement,
+                &program.symbols,
+                expected_identifier.0.clone()
+            ));
+
+            let value = match statement {
+                Statement::Let(ls) => &ls.value,
+                s => panic!("Statement is not Let, got {:?}", s),
+            };
+            assert_eq!(
+                value.string(),
+                *expected_value,
+                "let_statement.value wrong, got {:?}",
+                value
+            );
         }
+
+        let value = match &program.statements[0] {
+            Statement::Let(ls) => ls.value.clone(),
+            s => panic!("Statement is not Let, got {:?}", s),
+        };
+        assert!(test_integer_literal(value, 5));
     }
 
-    fn test_let_statement(s: &Statement, name: String) -> bool {
+    fn test_let_statement(s: &Statement, symbols: &Interner, name: String) -> bool {
         if s.token_literal() != "let" {
             println!("token_literal is not 'let', got {}", s.token_literal());
             return false;
         }
 
         if let Statement::Let(statement_data) = s {
-            if statement_data.name.get_expression().unwrap().value != name {
+            let symbol = statement_data.name.get_expression().unwrap().symbol;
+            if symbols.resolve(symbol) != name {
                 println!(
-                    "let_statement.name.value not {}, got {}",
+                    "let_statement.name.symbol not {}, got {}",
                     name,
-                    statement_data.name.get_expression().unwrap().value
+                    symbols.resolve(symbol)
                 );
                 return false;
             }
@@ -407,7 +905,41 @@ let foobar = 838383;
         }
 
         println!("Statement is not Let, got {:?}", s);
-        return false;
+        false
+    }
+
+    #[test]
+    fn test_let_statement_doc_comments() {
+        let input = r#"
+/// Dropped, since it isn't followed by a let.
+5;
+/// The answer.
+/// Don't change it.
+let answer = 42;
+let undocumented = 1 /// Dropped, since it's about the line it's on.
+let also_undocumented = 2
+"#;
+        let l = Lexer::new(input);
+        let mut p = Parser::new(l);
+
+        let program = p.parse_program();
+        check_parser_errors(p);
+
+        let docs: Vec<Vec<&str>> = program
+            .statements
+            .iter()
+            .filter_map(|s| match s {
+                Statement::Let(ls) => Some(ls.docs.iter().map(|doc| doc.as_ref()).collect()),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(
+            docs,
+            vec![vec![" The answer.", " Don't change it."], vec![], vec![]],
+            "let statement docs wrong, got {:?}",
+            docs
+        );
     }
 
     #[test]
@@ -416,37 +948,50 @@ let foobar = 838383;
 return 5;
 return 10;
 return 993322;
+return add * 2 + 1;
 "#;
 
-        let l = Lexer::new(input.to_string());
+        let l = Lexer::new(input);
         let mut p = Parser::new(l);
 
         let program = p.parse_program();
         check_parser_errors(p);
 
         assert!(
-            program.statements.len() == 3,
-            "Program.statements does not contain 3 statements, got: {}. Statements: {:?}",
+            program.statements.len() == 4,
+            "Program.statements does not contain 4 statements, got: {}. Statements: {:?}",
             program.statements.len(),
             program.statements
         );
 
+        let expected_values = ["5", "10", "993322", "((add * 2) + 1)"];
         let mut fail_count = 0;
 
-        program.statements.iter().for_each(|statement| {
-            if statement.token_literal() != "return" {
-                println!(
-                    "return_statement.token_literal not 'return', got: {}",
-                    statement.token_literal()
-                );
-                fail_count += 1;
-            }
-            if let Statement::Return(_) = statement {
-            } else {
-                println!("statement is not a ReturnStatement. Got {:?}", statement);
-                fail_count += 1;
-            }
-        });
+        program
+            .statements
+            .iter()
+            .zip(expected_values)
+            .for_each(|(statement, expected_value)| {
+                if statement.token_literal() != "return" {
+                    println!(
+                        "return_statement.token_literal not 'return', got: {}",
+                        statement.token_literal()
+                    );
+                    fail_count += 1;
+                }
+                if let Statement::Return(rs) = statement {
+                    if rs.value.string() != expected_value {
+                        println!(
+                            "return_statement.value not {}, got: {:?}",
+                            expected_value, rs.value
+                        );
+                        fail_count += 1;
+                    }
+                } else {
+                    println!("statement is not a ReturnStatement. Got {:?}", statement);
+                    fail_count += 1;
+                }
+            });
         assert_eq!(
             fail_count, 0,
             "More than one return statement test failed, check logs above this."
@@ -456,7 +1001,7 @@ return 993322;
     fn extract_expression(program: Program) -> Expression {
         let stmt = program
             .statements
-            .get(0)
+            .first()
             .expect("Did not have any statements.");
 
         let expression_stmt = match stmt {
@@ -467,14 +1012,14 @@ return 993322;
             ),
         };
 
-        expression_stmt.expression.as_ref().unwrap().clone()
+        expression_stmt.expression.clone()
     }
 
     #[test]
     fn test_identifier_expression() {
         let input = "foobar;";
 
-        let l = Lexer::new(input.to_string());
+        let l = Lexer::new(input);
         let mut p = Parser::new(l);
         let program = p.parse_program();
         check_parser_errors(p);
@@ -487,6 +1032,7 @@ return 993322;
             program.statements
         );
 
+        let symbols = program.symbols.clone();
         let ident_expression = extract_expression(program);
         let ident = match ident_expression {
             Expression::Identifier(ref i) => i,
@@ -494,9 +1040,10 @@ return 993322;
         };
 
         assert_eq!(
-            ident.value, "foobar",
-            "ident.value not 'foobar', got {}",
-            ident.value
+            symbols.resolve(ident.symbol),
+            "foobar",
+            "ident.symbol not 'foobar', got {}",
+            symbols.resolve(ident.symbol)
         );
         assert_eq!(
             ident_expression.token_literal(),
@@ -506,11 +1053,42 @@ return 993322;
         );
     }
 
+    #[test]
+    fn test_identifiers_share_symbols() {
+        let input = "x + x * y;";
+
+        let l = Lexer::new(input);
+        let mut p = Parser::new(l);
+        let program = p.parse_program();
+        check_parser_errors(p);
+
+        let x = program.symbols.get("x").expect("x not interned");
+        let y = program.symbols.get("y").expect("y not interned");
+        assert_ne!(x, y);
+
+        let symbol = |e: &Expression| match e {
+            Expression::Identifier(i) => i.symbol,
+            e => panic!("expression not Identifier, got {:?}", e),
+        };
+        let symbols = match extract_expression(program) {
+            Expression::InfixExpression(sum) => match *sum.right {
+                Expression::InfixExpression(product) => [
+                    symbol(&sum.left),
+                    symbol(&product.left),
+                    symbol(&product.right),
+                ],
+                e => panic!("expression not InfixExpression, got {:?}", e),
+            },
+            e => panic!("expression not InfixExpression, got {:?}", e),
+        };
+        assert_eq!(symbols, [x, x, y]);
+    }
+
     #[test]
     fn test_integer_literal_expression() {
         let input = "5;";
 
-        let l = Lexer::new(input.to_string());
+        let l = Lexer::new(input);
         let mut p = Parser::new(l);
         let program = p.parse_program();
         check_parser_errors(p);
@@ -530,10 +1108,9 @@ return 993322;
         };
 
         assert_eq!(
-            integer_literal.value.unwrap(),
-            5,
+            integer_literal.value, 5,
             "literal.value not 5, got {}",
-            integer_literal.value.unwrap()
+            integer_literal.value
         );
         assert_eq!(
             integer_literal_expression.token_literal(),
@@ -543,38 +1120,209 @@ return 993322;
         );
     }
 
-    struct PrefixTest {
-        input: String,
-        operator: String,
-        integer_value: i64,
-    }
-    impl PrefixTest {
-        fn new(input: &str, operator: &str, integer_value: i64) -> PrefixTest {
-            PrefixTest {
-                input: input.to_string(),
-                operator: operator.to_string(),
-                integer_value,
-            }
-        }
-    }
     #[test]
-    fn test_parsing_prefix_expressions() {
-        let prefix_tests: Vec<PrefixTest> = vec![
-            PrefixTest::new("!5;", "!", 5),
-            PrefixTest::new("-15;", "-", 15),
+    fn test_number_literal_expressions() {
+        let tests = vec![
+            ("0xFF;", "255"),
+            ("0o17;", "15"),
+            ("0b1010;", "10"),
+            ("1_000_000;", "1000000"),
+            ("9223372036854775807;", "9223372036854775807"),
+            ("3.14;", "3.14"),
+            ("1e-9;", "1e-9"),
+            ("2.0;", "2.0"),
+            ("1_000.5e1;", "10005.0"),
         ];
 
-        prefix_tests.into_iter().for_each(|test| {
-            let l = Lexer::new(test.input);
+        tests.into_iter().for_each(|(input, expected)| {
+            let l = Lexer::new(input);
             let mut p = Parser::new(l);
             let program = p.parse_program();
             check_parser_errors(p);
 
-            assert!(
-                program.statements.len() == 1,
-                "program.statements does not contain 1 statement. Got: {}. Statements: {:?}",
-                program.statements.len(),
-                program.statements
+            let expression = extract_expression(program);
+            assert_eq!(
+                expression.string(),
+                expected,
+                "{} parsed wrong, got {}",
+                input,
+                expression.string()
+            );
+        });
+    }
+
+    #[test]
+    fn test_out_of_range_number_literals() {
+        let tests = vec![
+            (
+                "9223372036854775808;",
+                "1:1: Integer literal 9223372036854775808 does not fit in a 64-bit integer",
+            ),
+            (
+                "0xFFFF_FFFF_FFFF_FFFF;",
+                "1:1: Integer literal 0xFFFF_FFFF_FFFF_FFFF does not fit in a 64-bit integer",
+            ),
+            ("1e400;", "1:1: Float literal 1e400 is out of range"),
+        ];
+
+        tests.into_iter().for_each(|(input, expected)| {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            p.parse_program();
+
+            let errors: Vec<String> = p
+                .errors()
+                .into_iter()
+                .map(|e| format!("{}: {}", e.span.start, e.message))
+                .collect();
+            assert_eq!(
+                errors.first().map(String::as_str),
+                Some(expected),
+                "wrong errors for {}, got {:?}",
+                input,
+                errors
+            );
+        });
+    }
+
+    #[test]
+    fn test_string_literal_expression() {
+        let input = r#""hello\t\"world\"";"#;
+
+        let l = Lexer::new(input);
+        let mut p = Parser::new(l);
+        let program = p.parse_program();
+        check_parser_errors(p);
+
+        let string_expression = extract_expression(program);
+        let string_literal = match string_expression {
+            Expression::StringLiteral(ref s) => s,
+            e => panic!("expression not StringLiteral, got {:?}", e),
+        };
+
+        assert_eq!(
+            string_literal.value, "hello\t\"world\"",
+            "literal.value not \"hello\\t\\\"world\\\"\", got {:?}",
+            string_literal.value
+        );
+        assert_eq!(
+            string_expression.string(),
+            input.trim_end_matches(';'),
+            "string() doesn't round trip, got {}",
+            string_expression.string()
+        );
+    }
+
+    #[test]
+    fn test_boolean_expressions() {
+        let tests = vec![("true;", true), ("false;", false)];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            let program = p.parse_program();
+            check_parser_errors(p);
+
+            let boolean = match extract_expression(program) {
+                Expression::Boolean(b) => b,
+                e => panic!("expression not Boolean, got {:?}", e),
+            };
+            assert_eq!(boolean.value, expected, "boolean.value wrong for {}", input);
+        }
+    }
+
+    #[test]
+    fn test_unclosed_parentheses() {
+        let l = Lexer::new("(1 + 2;");
+        let mut p = Parser::new(l);
+        p.parse_program();
+
+        let errors: Vec<String> = p.errors().into_iter().map(|e| e.to_string()).collect();
+        assert_eq!(errors, vec!["1:7: Expected `)`, found `;`"]);
+    }
+
+    #[test]
+    fn test_raw_and_multiline_string_literals() {
+        let tests = vec![
+            (r##"r#"C:\dir "quoted""#"##, r#""C:\\dir \"quoted\"""#),
+            ("\"\"\"\n    a\n      b\n    \"\"\"", r#""a\n  b""#),
+        ];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            let program = p.parse_program();
+            check_parser_errors(p);
+
+            let expression = extract_expression(program);
+            assert!(
+                matches!(expression, Expression::StringLiteral(_)),
+                "expression not StringLiteral, got {:?}",
+                expression
+            );
+            assert_eq!(expression.string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_template_literal_expressions() {
+        let tests = vec![
+            ("`Hello, ${name}!`", "`Hello, ${name}!`"),
+            ("``", "``"),
+            ("`${a + b * c}`", "`${(a + (b * c))}`"),
+            ("`a ${ `b ${c}` } d`", "`a ${`b ${c}`} d`"),
+            ("`\\${x} \\` \\\\ ${-y}`", "`\\${x} \\` \\\\ ${(-y)}`"),
+            ("`line one\nline two`", "`line one\nline two`"),
+        ];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            let program = p.parse_program();
+            check_parser_errors(p);
+
+            let expression = extract_expression(program);
+            assert!(
+                matches!(expression, Expression::Interpolation(_)),
+                "expression not Interpolation, got {:?}",
+                expression
+            );
+            assert_eq!(expression.string(), expected);
+        }
+    }
+
+    struct PrefixTest {
+        input: String,
+        operator: String,
+        integer_value: i64,
+    }
+    impl PrefixTest {
+        fn new(input: &str, operator: &str, integer_value: i64) -> PrefixTest {
+            PrefixTest {
+                input: input.to_string(),
+                operator: operator.to_string(),
+                integer_value,
+            }
+        }
+    }
+    #[test]
+    fn test_parsing_prefix_expressions() {
+        let prefix_tests: Vec<PrefixTest> = vec![
+            PrefixTest::new("!5;", "!", 5),
+            PrefixTest::new("-15;", "-", 15),
+        ];
+
+        prefix_tests.into_iter().for_each(|test| {
+            let l = Lexer::new(&test.input);
+            let mut p = Parser::new(l);
+            let program = p.parse_program();
+            check_parser_errors(p);
+
+            assert!(
+                program.statements.len() == 1,
+                "program.statements does not contain 1 statement. Got: {}. Statements: {:?}",
+                program.statements.len(),
+                program.statements
             );
 
             let prefix_expression = extract_expression(program);
@@ -595,17 +1343,16 @@ return 993322;
 
     fn test_integer_literal(il_expression: Expression, value: i64) -> bool {
         if let Expression::IntegerLiteral(ref int_literal) = il_expression {
-            if int_literal.value.unwrap() != value {
+            if int_literal.value != value {
                 println!(
                     "int_literal.value not {}, got: {}",
-                    value,
-                    int_literal.value.unwrap()
+                    value, int_literal.value
                 );
                 false
             } else if il_expression.token_literal() != value.to_string() {
                 println!(
                     "il_expression.token_literal not {}, got: {}",
-                    value.to_string(),
+                    value,
                     il_expression.token_literal()
                 );
                 false
@@ -648,10 +1395,13 @@ return 993322;
             InfixTest::new("5 < 5;", 5, "<", 5),
             InfixTest::new("5 == 5;", 5, "==", 5),
             InfixTest::new("5 != 5;", 5, "!=", 5),
+            InfixTest::new("5 <= 5;", 5, "<=", 5),
+            InfixTest::new("5 >= 5;", 5, ">=", 5),
+            InfixTest::new("5 % 5;", 5, "%", 5),
         ];
 
         infix_tests.into_iter().for_each(|test| {
-            let l = Lexer::new(test.input);
+            let l = Lexer::new(&test.input);
             let mut p = Parser::new(l);
             let program = p.parse_program();
             check_parser_errors(p);
@@ -730,6 +1480,18 @@ return 993322;
                 "5 < 4 != 3 > 4".to_string(),
                 "((5 < 4) != (3 > 4))".to_string(),
             ),
+            OperatorPrecedenceParsingTest::new(
+                "5 >= 4 == 3 <= 4".to_string(),
+                "((5 >= 4) == (3 <= 4))".to_string(),
+            ),
+            OperatorPrecedenceParsingTest::new(
+                "a + b % c * d".to_string(),
+                "(a + ((b % c) * d))".to_string(),
+            ),
+            OperatorPrecedenceParsingTest::new(
+                "-a % b <= c".to_string(),
+                "(((-a) % b) <= c)".to_string(),
+            ),
             OperatorPrecedenceParsingTest::new(
                 "3 + 4 * 5 == 3 * 1 + 4 * 5".to_string(),
                 "((3 + (4 * 5)) == ((3 * 1) + (4 * 5)))".to_string(),
@@ -738,10 +1500,66 @@ return 993322;
                 "3 + 4 * 5 == 3 * 1 + 4 * 5".to_string(),
                 "((3 + (4 * 5)) == ((3 * 1) + (4 * 5)))".to_string(),
             ),
+            OperatorPrecedenceParsingTest::new("true".to_string(), "true".to_string()),
+            OperatorPrecedenceParsingTest::new("false".to_string(), "false".to_string()),
+            OperatorPrecedenceParsingTest::new(
+                "3 > 5 == false".to_string(),
+                "((3 > 5) == false)".to_string(),
+            ),
+            OperatorPrecedenceParsingTest::new(
+                "3 < 5 == true".to_string(),
+                "((3 < 5) == true)".to_string(),
+            ),
+            OperatorPrecedenceParsingTest::new(
+                "!true == false".to_string(),
+                "((!true) == false)".to_string(),
+            ),
+            OperatorPrecedenceParsingTest::new(
+                "1 + (2 + 3) + 4".to_string(),
+                "((1 + (2 + 3)) + 4)".to_string(),
+            ),
+            OperatorPrecedenceParsingTest::new(
+                "(5 + 5) * 2".to_string(),
+                "((5 + 5) * 2)".to_string(),
+            ),
+            OperatorPrecedenceParsingTest::new(
+                "2 / (5 + 5)".to_string(),
+                "(2 / (5 + 5))".to_string(),
+            ),
+            OperatorPrecedenceParsingTest::new("-(5 + 5)".to_string(), "(-(5 + 5))".to_string()),
+            OperatorPrecedenceParsingTest::new(
+                "!(true == true)".to_string(),
+                "(!(true == true))".to_string(),
+            ),
+            OperatorPrecedenceParsingTest::new(
+                "((a + b) * (c - d)) % e".to_string(),
+                "(((a + b) * (c - d)) % e)".to_string(),
+            ),
+            OperatorPrecedenceParsingTest::new("(((a)))".to_string(), "a".to_string()),
+            OperatorPrecedenceParsingTest::new(
+                "-(-(a * (b + c)))".to_string(),
+                "(-(-(a * (b + c))))".to_string(),
+            ),
+            OperatorPrecedenceParsingTest::new(
+                "a + add(b * c) + d".to_string(),
+                "((a + add((b * c))) + d)".to_string(),
+            ),
+            OperatorPrecedenceParsingTest::new(
+                "add(a, b, 1, 2 * 3, 4 + 5, add(6, 7 * 8))".to_string(),
+                "add(a, b, 1, (2 * 3), (4 + 5), add(6, (7 * 8)))".to_string(),
+            ),
+            OperatorPrecedenceParsingTest::new(
+                "add(a + b + c * d / f + g)".to_string(),
+                "add((((a + b) + ((c * d) / f)) + g))".to_string(),
+            ),
+            OperatorPrecedenceParsingTest::new(
+                "-f(x) * g()(y)".to_string(),
+                "((-f(x)) * g()(y))".to_string(),
+            ),
         ];
 
         tests.into_iter().for_each(|test| {
-            let l = Lexer::new(test.input);
+            let l = Lexer::new(&test.input);
             let mut p = Parser::new(l);
             let program = p.parse_program();
             check_parser_errors(p);
@@ -756,4 +1574,601 @@ return 993322;
             assert_eq!(num_fail, 0);
         })
     }
+
+    #[test]
+    fn test_custom_operators() {
+        let operators = OperatorTable::default()
+            .with_infix(
+                TokenType::Assign,
+                InfixRule::binary(EQUALS - 5, Associativity::Right),
+            )
+            .with_prefix(TokenType::Asterisk, PrefixRule::unary(PREFIX))
+            .with_infix(
+                TokenType::Percent,
+                InfixRule::binary(SUM - 5, Associativity::Left),
+            )
+            // New operators, which the lexer has to read as well
+            .with_infix(
+                TokenType::Operator("@".into()),
+                InfixRule::binary(PRODUCT, Associativity::Left),
+            )
+            .with_infix(
+                TokenType::Operator("**".into()),
+                InfixRule::binary(PRODUCT + 5, Associativity::Right),
+            )
+            .with_infix(
+                TokenType::Operator("<=>".into()),
+                InfixRule::binary(EQUALS, Associativity::Left),
+            )
+            .with_prefix(TokenType::Operator("~".into()), PrefixRule::unary(PREFIX));
+        let operators = Arc::new(operators);
+
+        let tests = vec![
+            ("a = b = c == d", "(a = (b = (c == d)))"),
+            ("x = -y + 1", "(x = ((-y) + 1))"),
+            ("*a * *b", "((*a) * (*b))"),
+            ("a + b % c * d", "((a + b) % (c * d))"),
+            ("a - b - c", "((a - b) - c)"),
+            ("a @ b + c", "((a @ b) + c)"),
+            ("a ** b ** c * d", "((a ** (b ** c)) * d)"),
+            ("~a <=> b <= c", "((~a) <=> (b <= c))"),
+        ];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input).with_operators(Arc::clone(&operators));
+            let mut p = Parser::new(l);
+            let program = p.parse_program();
+            check_parser_errors(p);
+
+            assert_eq!(program.string(), expected, "input: {:?}", input);
+        }
+
+        let mut p = Parser::new(Lexer::new("*a"));
+        p.parse_program();
+        let errors = p.errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, EXPECTED_EXPRESSION);
+        assert!(!errors[0].expected.contains(&TokenType::Asterisk));
+
+        // Only the lexer given the table reads the new operators
+        for (input, operators) in [
+            ("a @ b", OperatorTable::shared_default()),
+            ("a ^ b", operators),
+        ] {
+            let mut p = Parser::new(Lexer::new(input).with_operators(operators));
+            p.parse_program();
+            let errors = p.errors();
+            assert_eq!(errors.len(), 1);
+            assert_eq!(errors[0].code, UNEXPECTED_CHARACTER);
+            assert_eq!(errors[0].span.start.offset, 2);
+        }
+    }
+
+    #[test]
+    fn test_if_expressions() {
+        let tests = vec![
+            ("if (x < y) { x }", "if (x < y) { x }"),
+            ("if x < y { x } else { y }", "if (x < y) { x } else { y }"),
+            (
+                "if x { let z = 1; z } else { }",
+                "if x { let z = 1; z } else { }",
+            ),
+            (
+                "if a { 1 } else if b { 2 } else if c { 3 } else { 4 }",
+                "if a { 1 } else if b { 2 } else if c { 3 } else { 4 }",
+            ),
+            (
+                "if x > 1 {\n  let y = x * 2\n  y\n} else {\n  return 0\n}\n",
+                "if (x > 1) { let y = (x * 2); y } else { return 0; }",
+            ),
+            (
+                "-if x { 1 } else { 2 } + 3",
+                "((-if x { 1 } else { 2 }) + 3)",
+            ),
+            ("let v = if !ok { 0 }", "let v = if (!ok) { 0 };"),
+        ];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            let program = p.parse_program();
+            check_parser_errors(p);
+
+            assert_eq!(program.statements.len(), 1, "input {:?}", input);
+            assert_eq!(program.string(), expected, "input {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_else_if_chain() {
+        let input = "if a { 1 } else if b { 2 } else { 3 }";
+
+        let l = Lexer::new(input);
+        let mut p = Parser::new(l);
+        let program = p.parse_program();
+        check_parser_errors(p);
+
+        let outer = match extract_expression(program) {
+            Expression::IfExpression(ie) => ie,
+            e => panic!("expression not IfExpression, got {:?}", e),
+        };
+        assert_eq!(outer.condition.string(), "a");
+        assert_eq!(outer.consequence.statements.len(), 1);
+        assert_eq!(outer.consequence.statements[0].string(), "1");
+
+        let inner = match outer.else_if() {
+            Some(Expression::IfExpression(ie)) => ie,
+            e => panic!("alternative not an else if, got {:?}", e),
+        };
+        assert_eq!(inner.condition.string(), "b");
+        assert_eq!(inner.consequence.string(), "{ 2 }");
+        assert!(inner.else_if().is_none());
+        assert_eq!(inner.alternative.as_ref().unwrap().string(), "{ 3 }");
+    }
+
+    /// Like in Go, a semicolon is inserted after a `}` at the end of a line,
+    /// so `else` can't start the next one.
+    #[test]
+    fn test_else_on_next_line() {
+        let l = Lexer::new("if x { 1 }\nelse { 2 }");
+        let mut p = Parser::new(l);
+        p.parse_program();
+
+        let errors: Vec<String> = p.errors().into_iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors.first().map(String::as_str),
+            Some(
+                "2:1: Expected an expression, found `else`\n  help: put `else` on the same line as the `}` before it"
+            )
+        );
+
+        let l = Lexer::new("if x { 1 }");
+        let mut p = Parser::new(l);
+        p.parse_program();
+        let errors: Vec<String> = p.errors().into_iter().map(|e| e.to_string()).collect();
+        assert!(errors.is_empty(), "unexpected errors {:?}", errors);
+
+        let l = Lexer::new("if x {\n  1\n");
+        let mut p = Parser::new(l);
+        p.parse_program();
+        let errors: Vec<String> = p.errors().into_iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "3:1: Expected `}` to close the block, found the end of the input\n  note: the block starts at 1:6"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_function_literal_parsing() {
+        let input = "fn(x, y) { x + y; }";
+
+        let l = Lexer::new(input);
+        let mut p = Parser::new(l);
+        let program = p.parse_program();
+        check_parser_errors(p);
+
+        let function = match extract_expression(program) {
+            Expression::FunctionLiteral(fl) => fl,
+            e => panic!("expression not FunctionLiteral, got {:?}", e),
+        };
+        let parameters: Vec<String> = function.parameters.iter().map(|p| p.string()).collect();
+        assert_eq!(parameters, vec!["x", "y"]);
+        assert_eq!(function.body.statements.len(), 1);
+        assert_eq!(function.body.statements[0].string(), "(x + y)");
+    }
+
+    #[test]
+    fn test_function_parameter_parsing() {
+        let tests = vec![
+            ("fn() {};", vec![]),
+            ("fn(x) {};", vec!["x"]),
+            ("fn(x, y, z) {};", vec!["x", "y", "z"]),
+            ("fn(\n  x,\n  y) {};", vec!["x", "y"]),
+            ("fn(x,) {};", vec!["x"]),
+            ("fn(\n  x,\n  y,\n) {};", vec!["x", "y"]),
+        ];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            let program = p.parse_program();
+            check_parser_errors(p);
+
+            let function = match extract_expression(program) {
+                Expression::FunctionLiteral(fl) => fl,
+                e => panic!("expression not FunctionLiteral, got {:?}", e),
+            };
+            let parameters: Vec<String> = function.parameters.iter().map(|p| p.string()).collect();
+            assert_eq!(parameters, expected, "input {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_call_expression_parsing() {
+        let input = "add(1, 2 * 3, 4 + 5);";
+
+        let l = Lexer::new(input);
+        let mut p = Parser::new(l);
+        let program = p.parse_program();
+        check_parser_errors(p);
+
+        let call = match extract_expression(program) {
+            Expression::CallExpression(ce) => ce,
+            e => panic!("expression not CallExpression, got {:?}", e),
+        };
+        assert_eq!(call.function.string(), "add");
+        let arguments: Vec<String> = call.arguments.iter().map(|a| a.string()).collect();
+        assert_eq!(arguments, vec!["1", "(2 * 3)", "(4 + 5)"]);
+    }
+
+    #[test]
+    fn test_functions_and_calls() {
+        let input = "let add = fn(x, y) {\n  x + y\n}\nadd(1, 2)\nfn(x) { x }(5);";
+
+        let l = Lexer::new(input);
+        let mut p = Parser::new(l);
+        let program = p.parse_program();
+        check_parser_errors(p);
+
+        let statements: Vec<_> = program.statements.iter().map(|s| s.string()).collect();
+        assert_eq!(
+            statements,
+            vec![
+                "let add = fn(x, y) { (x + y) };",
+                "add(1, 2)",
+                "fn(x) { x }(5)"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_automatic_semicolons() {
+        let tests = vec![
+            // An operator at the end of a line carries the expression on
+            ("a +\n  b * c\n", vec!["(a + (b * c))"]),
+            ("a ==\n  b !=\n  c", vec!["((a == b) != c)"]),
+            // but at the start of the next line it doesn't.
+            ("a\n- b\n", vec!["a", "(-b)"]),
+            ("a // c\n- b", vec!["a", "(-b)"]),
+            ("a /// doc\n- b", vec!["a", "(-b)"]),
+            ("-a\n!b", vec!["(-a)", "(!b)"]),
+            // Explicit semicolons still work, even next to inserted ones.
+            ("a; b\n;\nc;", vec!["a", "b", "c"]),
+            (
+                "let x = 5\nlet y = 10\nx",
+                vec!["let x = 5;", "let y = 10;", "x"],
+            ),
+            ("\"s\"\n-1.5\n", vec!["\"s\"", "(-1.5)"]),
+            ("return x *\n  y\nz", vec!["return (x * y);", "z"]),
+            // A call or parameter list can close on its own line after a
+            // trailing comma.
+            ("add(\n  1,\n  2,\n)\nx", vec!["add(1, 2)", "x"]),
+            ("add(1,)", vec!["add(1)"]),
+            (
+                "let f = fn(\n  a,\n  b,\n) { a }",
+                vec!["let f = fn(a, b) { a };"],
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            let program = p.parse_program();
+            check_parser_errors(p);
+
+            let statements: Vec<_> = program.statements.iter().map(|s| s.string()).collect();
+            assert_eq!(statements, expected, "input {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_error_positions() {
+        let input = "let x = 5;\n  let = 10;\n";
+
+        let l = Lexer::new(input);
+        let mut p = Parser::new(l);
+        p.parse_program();
+
+        let errors = p.errors();
+        let first = errors.first().expect("Expected a parser error.");
+        assert_eq!(
+            (first.span.start.line, first.span.start.column),
+            (2, 7),
+            "error does not point at 2:7, got {}",
+            first
+        );
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let input = "let x = (1 + 2\nlet = 5\nlet y = 99999999999999999999;\n";
+
+        let l = Lexer::new(input);
+        let mut p = Parser::new(l);
+        p.parse_program();
+        let errors = p.errors();
+
+        let codes: Vec<_> = errors.iter().map(|e| e.code).collect();
+        assert_eq!(
+            codes,
+            vec![UNEXPECTED_TOKEN, UNEXPECTED_TOKEN, INTEGER_OUT_OF_RANGE]
+        );
+        assert!(errors.iter().all(|e| e.severity == Severity::Error));
+
+        // The `)` is missing before the inserted semicolon at the end of line 1.
+        assert_eq!(errors[0].message, "Expected `)`, found the end of the line");
+        assert_eq!(errors[0].span.start, Position::new(14, 1, 15));
+        assert_eq!(errors[0].expected, vec![TokenType::RParen]);
+        assert_eq!(errors[0].found, Some(TokenType::Semicolon));
+        assert_eq!(errors[0].notes.len(), 1);
+
+        assert_eq!(errors[1].message, "Expected an identifier, found `=`");
+        assert_eq!(errors[1].expected, vec![TokenType::Ident]);
+        assert_eq!(errors[1].found, Some(TokenType::Assign));
+
+        assert_eq!(
+            errors[2].span,
+            Span::new(Position::new(31, 3, 9), Position::new(51, 3, 29))
+        );
+        assert!(errors[2].help.is_some());
+
+        let l = Lexer::new("1 + ;");
+        let mut p = Parser::new(l);
+        p.parse_program();
+        let errors = p.errors();
+        assert_eq!(errors.len(), 1, "wrong errors, got {:?}", errors);
+        assert_eq!(errors[0].code, EXPECTED_EXPRESSION);
+        assert_eq!(errors[0].message, "Expected an expression, found `;`");
+        assert!(errors[0].expected.contains(&TokenType::LParen));
+        assert_eq!(errors[0].found, Some(TokenType::Semicolon));
+    }
+
+    #[test]
+    fn test_error_recovery() {
+        let tests = vec![
+            (
+                "let x = ;\nlet y = 2;\ny",
+                vec!["1:9: Expected an expression, found `;`"],
+                vec!["let x = <error>;", "let y = 2;", "y"],
+            ),
+            (
+                "let = 10 + + 5;\nlet z = 1",
+                vec!["1:5: Expected an identifier, found `=`"],
+                vec!["<error>", "let z = 1;"],
+            ),
+            (
+                "let x = 5 +\nlet y = 6\nreturn y",
+                vec!["2:1: Expected an expression, found `let`"],
+                vec!["let x = <error>;", "let y = 6;", "return y;"],
+            ),
+            (
+                "f(1 2 3); g()",
+                vec!["1:5: Expected `)`, found an integer"],
+                vec!["<error>", "g()"],
+            ),
+            (
+                "} ) let a = 1",
+                vec!["1:1: Expected an expression, found `}`"],
+                vec!["<error>", "let a = 1;"],
+            ),
+            (
+                "if x {\n  1 +\n  let y = * 2\n  y\n}\nz",
+                vec![
+                    "3:3: Expected an expression, found `let`",
+                    "3:11: Expected an expression, found `*`",
+                ],
+                vec!["if x { <error> let y = <error>; y }", "z"],
+            ),
+            (
+                "fn(x, 1) { x }\nlet f = fn(x) { x ) }\nf",
+                vec![
+                    "1:7: Expected an identifier, found an integer",
+                    "2:19: Expected `;` or a line break, found `)`",
+                ],
+                vec!["<error>", "let f = fn(x) { x };", "f"],
+            ),
+            // Statements on the same line need a `;` between them
+            (
+                "let x = 1 let y = 2",
+                vec!["1:11: Expected `;` or a line break, found `let`"],
+                vec!["let x = 1;", "let y = 2;"],
+            ),
+            (
+                "5 5",
+                vec!["1:3: Expected `;` or a line break, found an integer"],
+                vec!["5"],
+            ),
+            (
+                "true false\nx",
+                vec!["1:6: Expected `;` or a line break, found `false`"],
+                vec!["true", "x"],
+            ),
+            (
+                "return 1 2",
+                vec!["1:10: Expected `;` or a line break, found an integer"],
+                vec!["return 1;"],
+            ),
+        ];
+
+        for (input, expected_errors, expected) in tests {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            let program = p.parse_program();
+
+            let errors: Vec<String> = p.errors().into_iter().map(|e| e.to_string()).collect();
+            assert_eq!(errors, expected_errors, "wrong errors for {:?}", input);
+
+            let statements: Vec<_> = program.statements.iter().map(|s| s.string()).collect();
+            assert_eq!(statements, expected, "wrong program for {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_lex_errors_do_not_cascade() {
+        let tests = vec![
+            ("5 @ + 3;", "1:3: Unexpected character '@'", "5"),
+            (
+                "let x = 1 + @ 3;",
+                "1:13: Unexpected character '@'",
+                "let x = <error>;",
+            ),
+            ("@ 5;", "1:1: Unexpected character '@'", "<error>"),
+            (
+                "0b12;",
+                "1:1: Malformed number 0b12: digit out of range for the number's base",
+                "<error>",
+            ),
+            ("\"abc", "1:1: Unterminated string literal", "\"abc\""),
+        ];
+
+        tests
+            .into_iter()
+            .for_each(|(input, expected_error, expected)| {
+                let l = Lexer::new(input);
+                let mut p = Parser::new(l);
+                let program = p.parse_program();
+
+                let errors: Vec<String> = p.errors().into_iter().map(|e| e.to_string()).collect();
+                assert_eq!(errors, vec![expected_error], "wrong errors for {}", input);
+                assert_eq!(program.string(), expected, "wrong program for {}", input);
+            });
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+
+        let input = nested(MAX_NESTING - 1);
+        let mut p = Parser::new(Lexer::new(&input));
+        let program = p.parse_program();
+        check_parser_errors(p);
+        assert_eq!(program.string(), "1");
+
+        let input = nested(MAX_NESTING);
+        let mut p = Parser::new(Lexer::new(&input));
+        let program = p.parse_program();
+        let errors = p.errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, NESTING_TOO_DEEP);
+        assert_eq!(errors[0].span.start.offset, MAX_NESTING);
+        assert_eq!(program.string(), "<error>");
+
+        // Each `else if` is a level deeper than the one before it
+        let chain = |links: usize| "if a { 1 } else ".repeat(links) + "{ 2 }";
+        let input = chain(100);
+        let mut p = Parser::new(Lexer::new(&input));
+        p.parse_program();
+        check_parser_errors(p);
+
+        let input = chain(MAX_NESTING);
+        let mut p = Parser::new(Lexer::new(&input));
+        p.parse_program();
+        let errors = p.errors();
+        assert!(!errors.is_empty());
+        assert!(errors.iter().all(|e| e.code == NESTING_TOO_DEEP));
+    }
+
+    /// Malformed input has to come back as errors, never as a panic or a
+    /// parser that doesn't finish.
+    #[test]
+    fn test_malformed_input() {
+        let corpus = [
+            "-;",
+            "5 +",
+            "!",
+            "- - -",
+            "let",
+            "let x",
+            "let x =",
+            "let x = ;",
+            "let = 5;",
+            "let 5 = x;",
+            "return",
+            "return;",
+            "return +;",
+            "let x = 5 *",
+            "1 + + 2;",
+            "* 3;",
+            ")",
+            "}",
+            "{",
+            "`${",
+            "`${}`",
+            "`${ 1 + }`",
+            "`${ let }`",
+            "\"abc",
+            "r#\"abc\"",
+            "\"\"\"abc",
+            "\"\"\"é",
+            "x\"\"\"é",
+            "\"\"\"\\é\"\"\"",
+            "/* abc",
+            "0x;",
+            "99999999999999999999;",
+            "1e999;",
+            "let x = @;",
+            "fn fn fn",
+            "if else",
+            "if",
+            "if x",
+            "if x {",
+            "if { 1 }",
+            "if x { 1 } else",
+            "if x { 1 } else if",
+            "if x { 1 }\nelse { 2 }",
+            "if x { let }",
+            "fn",
+            "fn(",
+            "fn(x",
+            "fn(1) { }",
+            "fn(x) x",
+            "fn(x) { x",
+            "add(",
+            "add(1, 2",
+            "add(,)",
+            "add(1,,)",
+            "add(\n  1,\n  2\n)",
+            "add(1\n, 2)",
+        ];
+
+        // Nesting deep enough to overflow the stack without a limit
+        let deep = [
+            "(".repeat(100_000),
+            "-".repeat(100_000) + "1",
+            "!(".repeat(50_000),
+            "a(".repeat(100_000),
+            "1 + (".repeat(100_000),
+            "if x { ".repeat(100_000),
+            "if a {1} else ".repeat(100_000) + "{1}",
+            "fn() { ".repeat(100_000),
+            "`${".repeat(100_000),
+        ];
+
+        for input in corpus
+            .iter()
+            .copied()
+            .chain(deep.iter().map(String::as_str))
+        {
+            let l = Lexer::new(input);
+            let mut p = Parser::new(l);
+            p.parse_program();
+
+            assert!(
+                !p.errors().is_empty(),
+                "expected errors for {:?}, got none",
+                &input[..input.len().min(20)]
+            );
+        }
+
+        // Every prefix of a valid program, cut off at each char boundary.
+        let program = "/// Docs\nlet x = -5 + `a ${ b * (c) } d`;\nreturn !x <= 0x1F % 2.5e3\nlet y = r#\"é\"#;\nlet z = \"\"\"é\\\"\"\"\"";
+        for (end, _) in program.char_indices() {
+            let l = Lexer::new(&program[..end]);
+            let mut p = Parser::new(l);
+            p.parse_program();
+            p.errors();
+        }
+    }
 }
@@ -1,9 +1,12 @@
-use crate::{lexer::Lexer, token::TokenType};
-use std::io::{self, stdout, Write};
+use rusty_monkey::{keywords::KeywordTable, lexer::Lexer};
+use std::{
+    io::{self, stdout, Write},
+    sync::Arc,
+};
 
 const PROMPT: &str = ">> ";
 
-pub fn start() {
+pub fn start(keywords: Arc<KeywordTable>) {
     let mut stdout = stdout();
 
     println!("Rusty Monkey Programming Languague v0.1.0");
@@ -14,18 +17,19 @@ pub fn start() {
 
         let mut input = String::new();
 
-        io::stdin()
+        let read = io::stdin()
             .read_line(&mut input)
             .expect("Failed to read user input.");
+        if read == 0 {
+            return;
+        }
 
-        let mut l = Lexer::new(input);
-
-        loop {
-            let tok = l.next_token();
-            if tok.token_type == TokenType::Eof {
-                break;
-            }
+        let mut l = Lexer::new(&input).with_keywords(Arc::clone(&keywords));
+        for tok in l.by_ref() {
             println!("{:?}", tok);
         }
+        for error in l.errors() {
+            println!("{}", error);
+        }
     }
 }
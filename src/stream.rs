@@ -0,0 +1,476 @@
+use std::{
+    io::{self, BufRead},
+    mem,
+    sync::Arc,
+};
+
+use crate::{
+    keywords::KeywordTable,
+    lexer::{LexError, Lexer, Mode},
+    operators::OperatorTable,
+    symbol::Interner,
+    token::{Position, Span, Token, TokenType},
+};
+
+/// A lexer over a `BufRead`, for inputs too big to read into memory first.
+///
+/// Only a small window of the input is kept in `buffer`, starting from the
+/// token currently being lexed. Each token is lexed by an ordinary `Lexer` over
+/// that window. If the lexer had to look at the end of the window to make out
+/// the token, we can't tell whether it really ends there or carries on in the
+/// next chunk, so we read more and lex it again. How far the lexer looks can
+/// be a long way past the token's start, e.g. for the `#`s of a raw string.
+///
+/// Tokens own their text since the window they were lexed from moves on.
+/// Identifiers are interned only once their token is complete, so a name cut
+/// in half by the end of a chunk never gets a symbol of its own.
+/// Errors from the reader, including input that isn't valid UTF-8, are reported
+/// as `LexError::Io` alongside the other lexical errors. Each invalid UTF-8
+/// sequence is lexed as a U+FFFD replacement char, which is reported only the
+/// once. Spans and positions are still byte offsets into the input as read,
+/// even though a replacement char is rarely as long as what it replaced.
+pub struct StreamLexer<R> {
+    reader: R,
+    buffer: String,                          // the window of input we're lexing
+    base: usize,                             // offset in the whole input of the start of `buffer`
+    pending: Vec<u8>,                        // bytes read that don't make up a whole char yet
+    replaced: Vec<(usize, isize)>,           // U+FFFDs put in, see `input_position`
+    resume: Position,                        // where the next token starts, in the whole input
+    modes: Vec<Mode>,                        // the lexer's template modes at `resume`
+    previous: Option<(TokenType, Position)>, // type and end of the last token
+    at_eof: bool,                            // whether `buffer` runs to the end of the input
+    interner: Interner,
+    keywords: Arc<KeywordTable>,
+    operators: Arc<OperatorTable>,
+    errors: Vec<LexError>,
+}
+
+impl<R: BufRead> StreamLexer<R> {
+    pub fn new(reader: R) -> StreamLexer<R> {
+        StreamLexer {
+            reader,
+            buffer: String::new(),
+            base: 0,
+            pending: Vec::new(),
+            replaced: Vec::new(),
+            resume: Position::new(0, 1, 1),
+            modes: Vec::new(),
+            previous: None,
+            at_eof: false,
+            interner: Interner::new(),
+            keywords: KeywordTable::shared_default(),
+            operators: OperatorTable::shared_default(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Makes the lexer read keywords from `keywords`, like
+    /// `Lexer::with_keywords`.
+    pub fn with_keywords(mut self, keywords: Arc<KeywordTable>) -> StreamLexer<R> {
+        self.keywords = keywords;
+        self
+    }
+
+    /// Makes the lexer read the operators in `operators` too, like
+    /// `Lexer::with_operators`.
+    pub fn with_operators(mut self, operators: Arc<OperatorTable>) -> StreamLexer<R> {
+        self.operators = operators;
+        self
+    }
+
+    pub fn errors(&self) -> &[LexError] {
+        &self.errors
+    }
+
+    pub fn interner(&self) -> &Interner {
+        &self.interner
+    }
+
+    pub fn take_interner(&mut self) -> Interner {
+        mem::take(&mut self.interner)
+    }
+
+    pub fn next_token(&mut self) -> Token<'static> {
+        loop {
+            let start = Position {
+                offset: self.resume.offset - self.base,
+                ..self.resume
+            };
+            // A template can have started before the start of the buffer, so
+            // its position in the buffer can be negative.
+            let base = self.base as isize;
+            let modes = self.modes.iter().map(|mode| mode.clone().offset_by(-base));
+            let mut l = Lexer::new_at(&self.buffer, start, false)
+                .with_modes(modes.collect())
+                .with_keywords(Arc::clone(&self.keywords))
+                .with_operators(Arc::clone(&self.operators));
+            if let Some((token_type, end)) = &self.previous {
+                l = l.with_previous(token_type, *end);
+            }
+            let tok = l.next_token();
+
+            if self.at_eof || l.peeked() < self.buffer.len() {
+                let errors = l.errors().iter().map(|e| e.clone().offset_by(base));
+                for error in errors {
+                    if !self.is_replacement(&error) {
+                        let error = error.map_positions(|position| self.input_position(position));
+                        self.errors.push(error);
+                    }
+                }
+                self.modes = l
+                    .modes()
+                    .iter()
+                    .map(|mode| mode.clone().offset_by(base))
+                    .collect();
+
+                let mut tok = tok.into_owned();
+                tok.span = tok.span.offset_by(base);
+                self.resume = tok.span.end;
+                self.previous = Some((tok.token_type.clone(), tok.span.end));
+                if tok.symbol.is_some() {
+                    tok.symbol = Some(self.interner.intern(&tok.literal));
+                }
+                tok.span = Span::new(
+                    self.input_position(tok.span.start),
+                    self.input_position(tok.span.end),
+                );
+                return tok;
+            }
+
+            self.fill_buffer();
+        }
+    }
+
+    /// Drops the text we've finished with from the front of the buffer and
+    /// reads at least as much again onto the end, so that a token spanning many
+    /// chunks isn't lexed over and over for every chunk.
+    fn fill_buffer(&mut self) {
+        let finished = self.resume.offset - self.base;
+        self.buffer.drain(..finished);
+        self.base += finished;
+
+        let wanted = self.buffer.len().max(1) * 2;
+        while self.buffer.len() < wanted && !self.at_eof {
+            match self.reader.fill_buf() {
+                Ok([]) => {
+                    self.at_eof = true;
+                    if !self.pending.is_empty() {
+                        let len = self.pending.len();
+                        self.pending.clear();
+                        self.replace_invalid(len);
+                    }
+                }
+                Ok(bytes) => {
+                    self.pending.extend_from_slice(bytes);
+                    let read = bytes.len();
+                    self.reader.consume(read);
+                    self.decode_pending();
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    // There's no telling what the reader would give us next, so
+                    // treat this as the end of the input.
+                    self.at_eof = true;
+                    self.error(e.kind(), e.to_string());
+                }
+            }
+        }
+    }
+
+    /// Moves as much of `pending` into the buffer as is valid UTF-8, keeping
+    /// back a char that's been cut off by the end of the chunk. Invalid bytes
+    /// are reported and replaced.
+    fn decode_pending(&mut self) {
+        loop {
+            let (valid, invalid) = match std::str::from_utf8(&self.pending) {
+                Ok(text) => (text.len(), None),
+                Err(e) => (e.valid_up_to(), e.error_len()),
+            };
+            self.buffer
+                .push_str(&String::from_utf8_lossy(&self.pending[..valid]));
+
+            match invalid {
+                Some(len) => {
+                    self.pending.drain(..valid + len);
+                    self.replace_invalid(len);
+                }
+                None => {
+                    self.pending.drain(..valid);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Reports `len` bytes of invalid UTF-8 at the end of the buffer and puts
+    /// a U+FFFD in their place.
+    fn replace_invalid(&mut self, len: usize) {
+        self.error(
+            io::ErrorKind::InvalidData,
+            "stream did not contain valid UTF-8".to_string(),
+        );
+
+        let ahead = self.replaced.last().map_or(0, |(_, ahead)| *ahead);
+        self.buffer.push(char::REPLACEMENT_CHARACTER);
+        let end = self.base + self.buffer.len();
+        let ahead = ahead + len as isize - char::REPLACEMENT_CHARACTER.len_utf8() as isize;
+        self.replaced.push((end, ahead));
+    }
+
+    /// Whether `error` is the lexer complaining about one of the U+FFFDs we
+    /// put in, which has been reported already.
+    fn is_replacement(&self, error: &LexError) -> bool {
+        match error {
+            LexError::UnexpectedCharacter { ch, span } => {
+                *ch == char::REPLACEMENT_CHARACTER
+                    && self
+                        .replaced
+                        .binary_search_by_key(&span.end.offset, |(end, _)| *end)
+                        .is_ok()
+            }
+            _ => false,
+        }
+    }
+
+    /// Where `position`, in the text we've lexed, is in the input as read.
+    /// `replaced` holds the end of each U+FFFD we put in, along with how far
+    /// ahead the input is of our text from there on.
+    fn input_position(&self, position: Position) -> Position {
+        let replaced = self
+            .replaced
+            .partition_point(|(end, _)| *end <= position.offset);
+        match replaced {
+            0 => position,
+            _ => position.offset_by(self.replaced[replaced - 1].1),
+        }
+    }
+
+    /// Records a problem reading the input, at the end of what we've read.
+    fn error(&mut self, kind: io::ErrorKind, message: String) {
+        let position = self
+            .resume
+            .advanced_over(&self.buffer[self.resume.offset - self.base..]);
+        let position = self.input_position(position);
+        self.errors.push(LexError::Io {
+            kind,
+            message,
+            position,
+        });
+    }
+}
+
+impl<R: BufRead> Iterator for StreamLexer<R> {
+    type Item = Token<'static>;
+
+    fn next(&mut self) -> Option<Token<'static>> {
+        let tok = self.next_token();
+        if tok.token_type == TokenType::Eof {
+            None
+        } else {
+            Some(tok)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StreamLexer;
+    use crate::{
+        keywords::KeywordTable,
+        lexer::{LexError, Lexer},
+        operators::{Associativity, InfixRule, OperatorTable, EQUALS},
+        token::{Position, TokenType},
+    };
+    use std::{
+        io::{self, BufReader, Read},
+        sync::Arc,
+    };
+
+    const INPUT: &str = "/// Greets everyone
+let grüße = \"Hallo, \\u{1F600} Welt!\";
+let pi = 3.14 * 1e-9 + 0xFF_FF;
+/* a /* nested */ comment */
+let t = `a ${ `b ${ {c} }` } \\` d`;
+if (pi <= 10 == !true) { return grüße; } else { x % @ 2 }
+\"a string
+over lines\" 0x ; `${ `x";
+
+    /// Streaming `input` through buffers of every small size should give
+    /// exactly what lexing it all at once does.
+    fn check_stream(input: &str) {
+        let mut l = Lexer::new(input);
+        let expected: Vec<_> = l.by_ref().map(|tok| tok.into_owned()).collect();
+        let expected_errors = l.errors().to_vec();
+
+        for capacity in 1..=16 {
+            let mut s = StreamLexer::new(BufReader::with_capacity(capacity, input.as_bytes()));
+            let tokens: Vec<_> = s.by_ref().collect();
+
+            assert_eq!(
+                format!("{:?}", tokens),
+                format!("{:?}", expected),
+                "tokens differ for {:?} with a buffer of {} bytes",
+                input,
+                capacity
+            );
+            assert_eq!(
+                s.errors(),
+                expected_errors,
+                "errors differ for {:?} with a buffer of {} bytes",
+                input,
+                capacity
+            );
+        }
+    }
+
+    #[test]
+    fn test_stream_matches_lexer() {
+        check_stream(INPUT);
+    }
+
+    /// The lexer can look a long way past the start of a token before it knows
+    /// what the token is, further than any of the buffers here.
+    #[test]
+    fn test_stream_long_lookahead() {
+        check_stream("r#########\"x\"######### + 1");
+        check_stream("r####################x");
+        check_stream("let s = r###\"a \"## b\"###;");
+        check_stream("\"\"\"\n  long enough to span buffers\n  \"\"\" + 1");
+        check_stream("\"\"\" never closed, \\\"\"\" still not");
+    }
+
+    #[test]
+    fn test_stream_operators() {
+        let rule = InfixRule::binary(EQUALS, Associativity::Left);
+        let operators =
+            Arc::new(OperatorTable::default().with_infix(TokenType::Operator("<=>>".into()), rule));
+        let input = "a <=>> b <= c";
+
+        for capacity in 1..=8 {
+            let s = StreamLexer::new(BufReader::with_capacity(capacity, input.as_bytes()))
+                .with_operators(Arc::clone(&operators));
+            let types: Vec<_> = s.map(|tok| tok.token_type).collect();
+            assert_eq!(
+                types,
+                vec![
+                    TokenType::Ident,
+                    TokenType::Operator("<=>>".into()),
+                    TokenType::Ident,
+                    TokenType::LtEq,
+                    TokenType::Ident
+                ],
+                "wrong tokens with a buffer of {} bytes",
+                capacity
+            );
+        }
+    }
+
+    #[test]
+    fn test_stream_large_input() {
+        let input = "let x = \"a string\" + 12345; // comment\n".repeat(10_000);
+        let s = StreamLexer::new(BufReader::with_capacity(64, input.as_bytes()));
+        let l = Lexer::new(&input);
+
+        assert!(s
+            .zip(l)
+            .all(|(a, b)| a.literal == b.literal && a.span == b.span));
+    }
+
+    #[test]
+    fn test_stream_invalid_utf8() {
+        let input: &[u8] = b"let \xff x = \"\xe2\x82\";\nlet y = z\xc0;";
+
+        for capacity in 1..=8 {
+            let mut s = StreamLexer::new(BufReader::with_capacity(capacity, input));
+            let tokens: Vec<_> = s.by_ref().collect();
+            let types: Vec<_> = tokens.iter().map(|tok| &tok.token_type).collect();
+            assert_eq!(
+                types,
+                vec![
+                    &TokenType::Let,
+                    &TokenType::Illegal,
+                    &TokenType::Ident,
+                    &TokenType::Assign,
+                    &TokenType::String,
+                    &TokenType::Semicolon,
+                    &TokenType::Let,
+                    &TokenType::Ident,
+                    &TokenType::Assign,
+                    &TokenType::Ident,
+                    &TokenType::Illegal,
+                    &TokenType::Semicolon,
+                ],
+                "wrong tokens with a buffer of {} bytes",
+                capacity
+            );
+
+            // Spans are still offsets into the bytes as read.
+            for tok in tokens
+                .iter()
+                .filter(|tok| tok.token_type != TokenType::String)
+            {
+                let text = &input[tok.span.start.offset..tok.span.end.offset];
+                match tok.token_type {
+                    TokenType::Illegal => assert!(std::str::from_utf8(text).is_err()),
+                    _ => assert_eq!(text, tok.literal.as_bytes()),
+                }
+            }
+            assert_eq!(tokens[4].literal, "\u{FFFD}");
+            assert_eq!(tokens[4].span.end.offset, 14);
+
+            let errors: Vec<String> = s.errors().iter().map(|e| e.to_string()).collect();
+            assert_eq!(
+                errors,
+                vec![
+                    "1:5: Could not read input: stream did not contain valid UTF-8",
+                    "1:12: Could not read input: stream did not contain valid UTF-8",
+                    "2:10: Could not read input: stream did not contain valid UTF-8",
+                ],
+                "wrong errors with a buffer of {} bytes",
+                capacity
+            );
+        }
+    }
+
+    #[test]
+    fn test_stream_keywords() {
+        let keywords = Arc::new(KeywordTable::dialect("es").unwrap());
+        let input = "sea x = verdadero;";
+        let s = StreamLexer::new(BufReader::with_capacity(2, input.as_bytes()))
+            .with_keywords(Arc::clone(&keywords));
+        let l = Lexer::new(input).with_keywords(keywords);
+
+        let types: Vec<_> = s.map(|tok| tok.token_type).collect();
+        assert_eq!(types, l.map(|tok| tok.token_type).collect::<Vec<_>>());
+        assert_eq!(types[0], TokenType::Let);
+    }
+
+    /// A reader that fails once it has handed out its data.
+    struct FailingReader<'a>(&'a [u8]);
+
+    impl Read for FailingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(io::Error::other("disk on fire"));
+            }
+            self.0.read(buf)
+        }
+    }
+
+    #[test]
+    fn test_stream_io_error() {
+        let reader = BufReader::with_capacity(4, FailingReader(b"let x =\n5"));
+        let mut s = StreamLexer::new(reader);
+        let literals: Vec<_> = s.by_ref().map(|tok| tok.literal).collect();
+
+        assert_eq!(literals, vec!["let", "x", "=", "5"]);
+        assert_eq!(
+            s.errors(),
+            vec![LexError::Io {
+                kind: io::ErrorKind::Other,
+                message: "disk on fire".to_string(),
+                position: Position::new(9, 2, 2),
+            }]
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+/// An interned identifier. Two symbols from the same `Interner` are equal
+/// exactly when the identifiers they stand for are, so comparing and hashing
+/// names is just comparing and hashing a `u32`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    pub fn as_u32(self) -> u32 {
+        self.0
+    }
+}
+
+/// Hands out a `Symbol` for every distinct identifier it sees and maps them
+/// back to text.
+///
+/// Each `Lexer` has its own interner, which the parser hands on to the
+/// `Program` it builds, so every symbol in a program can be resolved through
+/// `Program::symbols`.
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    symbols: HashMap<String, Symbol>,
+    names: Vec<String>, // indexed by symbol
+}
+
+impl Interner {
+    pub fn new() -> Interner {
+        Interner::default()
+    }
+
+    /// Returns the symbol for `name`, adding it if we haven't seen it before.
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(&symbol) = self.symbols.get(name) {
+            return symbol;
+        }
+
+        let symbol = Symbol(
+            u32::try_from(self.names.len()).expect("more than u32::MAX distinct identifiers"),
+        );
+        self.symbols.insert(name.to_string(), symbol);
+        self.names.push(name.to_string());
+        symbol
+    }
+
+    /// Returns the symbol for `name` if it has been interned, without adding it.
+    pub fn get(&self, name: &str) -> Option<Symbol> {
+        self.symbols.get(name).copied()
+    }
+
+    /// Returns the text of `symbol`.
+    ///
+    /// Panics if `symbol` came from a different interner and is out of range.
+    pub fn resolve(&self, symbol: Symbol) -> &str {
+        &self.names[symbol.0 as usize]
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Interner;
+
+    #[test]
+    fn test_interner() {
+        let mut interner = Interner::new();
+        let x = interner.intern("x");
+        let grüße = interner.intern("grüße");
+
+        assert_ne!(x, grüße);
+        assert_eq!(interner.intern("x"), x);
+        assert_eq!(interner.get("grüße"), Some(grüße));
+        assert_eq!(interner.get("y"), None);
+        assert_eq!(interner.resolve(x), "x");
+        assert_eq!(interner.resolve(grüße), "grüße");
+        assert_eq!(interner.len(), 2);
+    }
+}
@@ -1,13 +1,19 @@
-use std::str::{self, from_utf8};
+use crate::{
+    keywords::{KeywordTable, Word},
+    symbol::Symbol,
+};
+use std::{borrow::Cow, fmt, sync::Arc};
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum TokenType {
-    Illegal,
-    Eof, // '\0'
+    Illegal, // a malformed literal, already reported by the lexer
+    Eof,     // '\0'
 
     // Identifiers + literals
-    Ident, // add, foobar, x, y, ...
-    Int,   // 942109437
+    Ident,  // add, foobar, x, y, ...
+    Int,    // 942109437, 0xFF, 0o17, 0b1010, 1_000_000
+    Float,  // 3.14, 1e-9
+    String, // "foo bar", r#"raw"#, """multi-line"""
 
     // Operators
     Assign,   // =
@@ -16,11 +22,16 @@ pub enum TokenType {
     Bang,     // !
     Asterisk, // *
     Slash,    // /
+    Percent,  // %
     Lt,       // <
     Gt,       // >
+    LtEq,     // <=
+    GtEq,     // >=
     Eq,       // ==
     NotEq,    // !=
 
+    Operator(Arc<str>), // one added by an `OperatorTable`, e.g. @ or |>
+
     // Delimiters
     Comma,     // ,
     Semicolon, // ;
@@ -29,6 +40,16 @@ pub enum TokenType {
     LBrace,    // {
     RBrace,    // }
 
+    // Template strings, e.g. `Hello, ${name}!`
+    TemplateStart,      // ` at the start
+    TemplateText,       // Hello,
+    InterpolationStart, // ${
+    InterpolationEnd,   // }
+    TemplateEnd,        // ` at the end
+
+    // Comments
+    DocComment, // /// Adds two numbers.
+
     // Keywords
     Function, // fn
     Let,      // let
@@ -39,38 +60,204 @@ pub enum TokenType {
     Return,   // return
 }
 
+/// How a token type is described to users in error messages.
+impl fmt::Display for TokenType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            TokenType::Illegal => "an invalid token",
+            TokenType::Eof => "the end of the input",
+            TokenType::Ident => "an identifier",
+            TokenType::Int => "an integer",
+            TokenType::Float => "a float",
+            TokenType::String => "a string",
+            TokenType::Assign => "`=`",
+            TokenType::Plus => "`+`",
+            TokenType::Minus => "`-`",
+            TokenType::Bang => "`!`",
+            TokenType::Asterisk => "`*`",
+            TokenType::Slash => "`/`",
+            TokenType::Percent => "`%`",
+            TokenType::Lt => "`<`",
+            TokenType::Gt => "`>`",
+            TokenType::LtEq => "`<=`",
+            TokenType::GtEq => "`>=`",
+            TokenType::Eq => "`==`",
+            TokenType::NotEq => "`!=`",
+            TokenType::Operator(spelling) => return write!(f, "`{}`", spelling),
+            TokenType::Comma => "`,`",
+            TokenType::Semicolon => "`;`",
+            TokenType::LParen => "`(`",
+            TokenType::RParen => "`)`",
+            TokenType::LBrace => "`{`",
+            TokenType::RBrace => "`}`",
+            TokenType::TemplateStart => "a template string",
+            TokenType::TemplateText => "template text",
+            TokenType::InterpolationStart => "`${`",
+            TokenType::InterpolationEnd => "the `}` of an interpolation",
+            TokenType::TemplateEnd => "the end of a template string",
+            TokenType::DocComment => "a doc comment",
+            TokenType::Function => "`fn`",
+            TokenType::Let => "`let`",
+            TokenType::True => "`true`",
+            TokenType::False => "`false`",
+            TokenType::If => "`if`",
+            TokenType::Else => "`else`",
+            TokenType::Return => "`return`",
+        };
+        f.write_str(text)
+    }
+}
+
+/// A point in the source text.
+///
+/// `offset` is a byte offset into the input. `line` and `column` are 1-based,
+/// and columns count chars rather than bytes, so a tab or an emoji each take up
+/// a single column. Tokens that weren't produced by a `Lexer` use the default,
+/// all-zero position.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    pub fn new(offset: usize, line: usize, column: usize) -> Position {
+        Position {
+            offset,
+            line,
+            column,
+        }
+    }
+
+    /// The same position, `by` bytes further into the input. This wraps rather
+    /// than overflowing, so a position can be moved to before the start of a
+    /// piece of the input and back again.
+    pub fn offset_by(self, by: isize) -> Position {
+        Position {
+            offset: self.offset.wrapping_add_signed(by),
+            ..self
+        }
+    }
+
+    /// Where we end up after reading `text` starting from this position, using
+    /// the same line break rules as the lexer.
+    pub fn advanced_over(self, text: &str) -> Position {
+        let mut position = self;
+        let mut chars = text.chars().peekable();
+        while let Some(ch) = chars.next() {
+            position.offset += ch.len_utf8();
+            match ch {
+                '\r' if chars.peek() == Some(&'\n') => position.column += 1,
+                '\n' | '\r' => {
+                    position.line += 1;
+                    position.column = 1;
+                }
+                _ => position.column += 1,
+            }
+        }
+        position
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// The half-open range of source text `[start, end)` that a token covers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    pub fn new(start: Position, end: Position) -> Span {
+        Span { start, end }
+    }
+
+    /// The same span, `by` bytes further into the input.
+    pub fn offset_by(self, by: isize) -> Span {
+        Span::new(self.start.offset_by(by), self.end.offset_by(by))
+    }
+}
+
+/// A token produced by the `Lexer`.
+///
+/// `literal` borrows from the source text whenever it can, so lexing doesn't
+/// allocate for every token. It only owns its text when that text doesn't
+/// appear verbatim in the source, e.g. a string with escape sequences in it.
+///
+/// `symbol` is set on `Ident` tokens by the lexer, which interns every
+/// identifier it reads (see `Lexer::interner`).
+///
+/// `trivia` is only filled in by a lossless lexer (see `Lexer::new_lossless`).
+/// It's boxed so that tokens stay small when it isn't needed.
 #[derive(Debug, Clone)]
-pub struct Token {
+pub struct Token<'src> {
     pub token_type: TokenType,
-    pub literal: String,
+    pub literal: Cow<'src, str>,
+    pub span: Span,
+    pub symbol: Option<Symbol>,
+    pub trivia: Option<Box<Trivia<'src>>>,
 }
 
-impl Token {
-    pub fn new_from_str(token_type: TokenType, str: &str) -> Token {
-        let literal = str.to_string();
+/// The source text in and around a token, for tools that need to reproduce the
+/// input exactly.
+#[derive(Debug, Clone, Default)]
+pub struct Trivia<'src> {
+    pub leading: Cow<'src, str>,  // whitespace and comments before the token
+    pub lexeme: Cow<'src, str>,   // the token exactly as written in the source
+    pub trailing: Cow<'src, str>, // whitespace and comments after it on the same line
+}
+
+impl Trivia<'_> {
+    pub fn into_owned(self) -> Trivia<'static> {
+        Trivia {
+            leading: Cow::Owned(self.leading.into_owned()),
+            lexeme: Cow::Owned(self.lexeme.into_owned()),
+            trailing: Cow::Owned(self.trailing.into_owned()),
+        }
+    }
+}
+
+impl<'src> Token<'src> {
+    pub fn new(
+        token_type: TokenType,
+        literal: impl Into<Cow<'src, str>>,
+        span: Span,
+    ) -> Token<'src> {
         Token {
             token_type,
-            literal,
+            literal: literal.into(),
+            span,
+            symbol: None,
+            trivia: None,
         }
     }
-    pub fn new_from_byte(token_type: TokenType, byte: u8) -> Token {
-        let literal: String = from_utf8(&[byte]).unwrap().to_string();
+    pub fn new_from_str(token_type: TokenType, str: &'src str) -> Token<'src> {
+        Token::new(token_type, str, Span::default())
+    }
+
+    /// Detaches the token from the source text it was lexed from.
+    pub fn into_owned(self) -> Token<'static> {
         Token {
-            token_type,
-            literal,
+            token_type: self.token_type,
+            literal: Cow::Owned(self.literal.into_owned()),
+            span: self.span,
+            symbol: self.symbol,
+            trivia: self.trivia.map(|trivia| Box::new(trivia.into_owned())),
         }
     }
 
+    /// The type of `ident` under the default keyword table. Lexers can be
+    /// given other tables, see `Lexer::with_keywords`.
     pub fn lookup_ident(ident: &str) -> TokenType {
-        match ident {
-            "fn" => TokenType::Function,
-            "let" => TokenType::Let,
-            "true" => TokenType::True,
-            "false" => TokenType::False,
-            "if" => TokenType::If,
-            "else" => TokenType::Else,
-            "return" => TokenType::Return,
-            _ => TokenType::Ident,
+        match KeywordTable::shared_default().lookup(ident) {
+            Word::Keyword(token_type) => token_type,
+            Word::Reserved | Word::Ident => TokenType::Ident,
         }
     }
 }